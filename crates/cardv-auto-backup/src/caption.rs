@@ -0,0 +1,175 @@
+//! Message captions rendered from a user-defined template
+
+use std::time::Duration;
+
+use chrono::NaiveDateTime;
+
+use crate::layout::Category;
+
+pub const DEFAULT_TEMPLATE: &str =
+    "{category} {date} {time}\n{camera} | {duration} | {resolution} | {size}";
+
+/// The placeholders a template may use, shown as a hint in the settings
+pub const PLACEHOLDERS: &[&str] = &[
+    "{date}",
+    "{time}",
+    "{camera}",
+    "{category}",
+    "{duration}",
+    "{resolution}",
+    "{size}",
+];
+
+/// Everything a caption can tell about a recording
+#[derive(Debug, Clone)]
+pub struct CaptionInfo {
+    /// The camera's wall-clock time
+    pub recorded_at: NaiveDateTime,
+    pub camera: String,
+    pub category: Category,
+    /// Only known for videos
+    pub duration: Option<Duration>,
+    pub resolution: Option<(i32, i32)>,
+    pub size: u64,
+}
+
+/// Fills the `{placeholders}` in the template, unknown ones are kept as they are
+pub fn render(template: &str, info: &CaptionInfo) -> String {
+    let mut caption = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        caption.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let Some(end) = rest.find('}') else {
+            break;
+        };
+
+        let value = match &rest[1..end] {
+            "date" => info.recorded_at.format("%Y-%m-%d").to_string(),
+            "time" => info.recorded_at.format("%H:%M:%S").to_string(),
+            "camera" => info.camera.clone(),
+            "category" => info.category.tag().to_string(),
+            "duration" => info.duration.map(format_duration).unwrap_or_default(),
+            "resolution" => info
+                .resolution
+                .map(|(width, height)| format!("{width}x{height}"))
+                .unwrap_or_default(),
+            "size" => format_bytes(info.size),
+            _ => {
+                caption.push('{');
+                rest = &rest[1..];
+                continue;
+            }
+        };
+        caption.push_str(&value);
+        rest = &rest[end + 1..];
+    }
+    caption.push_str(rest);
+
+    caption.trim().to_string()
+}
+
+/// Appends the tags to the caption on lines of their own, within `max_len` characters
+///
+/// the tags tell what the message is, so it's the caption that is cut short if needed.
+pub fn with_tags(caption: &str, tags: &[String], max_len: usize) -> String {
+    let tags = tags
+        .iter()
+        .map(|tag| format!("\n{tag}"))
+        .collect::<String>();
+    let room = max_len.saturating_sub(tags.chars().count());
+
+    let mut caption = match caption.char_indices().nth(room) {
+        Some(_) => {
+            // room for the ellipsis
+            let end = caption
+                .char_indices()
+                .nth(room.saturating_sub(1))
+                .map_or(0, |(end, _)| end);
+            format!("{}…", caption[..end].trim_end())
+        }
+        None => caption.to_string(),
+    };
+    caption.push_str(&tags);
+    caption
+}
+
+pub fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["KB", "MB", "GB", "TB"];
+
+    if bytes < 1024 {
+        return format!("{bytes} B");
+    }
+
+    let mut value = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+
+    format!("{value:.1} {}", UNITS[unit])
+}
+
+pub fn format_duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    match (secs / 3600, secs / 60 % 60, secs % 60) {
+        (0, 0, secs) => format!("{secs}s"),
+        (0, mins, secs) => format!("{mins}m {secs}s"),
+        (hours, mins, _) => format!("{hours}h {mins}m"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn render_template() {
+        let info = CaptionInfo {
+            recorded_at: NaiveDateTime::parse_from_str("2024-03-26 15:30:12", "%Y-%m-%d %H:%M:%S")
+                .unwrap(),
+            camera: "VIOFO".into(),
+            category: Category::Event,
+            duration: Some(Duration::from_secs(61)),
+            resolution: Some((3840, 2160)),
+            size: 300 * 1024 * 1024,
+        };
+
+        assert_eq!(
+            render(
+                "{category} {date} {time} {camera} {duration} {resolution} {size} {unknown}",
+                &info
+            ),
+            "#event 2024-03-26 15:30:12 VIOFO 1m 1s 3840x2160 300.0 MB {unknown}"
+        );
+
+        let photo = CaptionInfo {
+            category: Category::Photo,
+            duration: None,
+            resolution: None,
+            ..info
+        };
+        assert_eq!(render("{category} {duration}", &photo), "#photo");
+    }
+
+    #[test]
+    fn cut_captions_short() {
+        let tags = ["#repaired".to_string(), "part 1/2".to_string()];
+        assert_eq!(
+            with_tags("short", &tags, 1024),
+            "short\n#repaired\npart 1/2"
+        );
+
+        // multi-byte characters are never cut in half
+        let caption = "ä".repeat(2000);
+        let cut = with_tags(&caption, &tags, 1024);
+        assert_eq!(cut.chars().count(), 1024);
+        assert!(cut.starts_with("ää"));
+        assert!(cut.ends_with("ä…\n#repaired\npart 1/2"));
+
+        let exact = "a".repeat(1024 - "\n#repaired\npart 1/2".len());
+        assert_eq!(with_tags(&exact, &tags, 1024).chars().count(), 1024);
+        assert!(!with_tags(&exact, &tags, 1024).contains('…'));
+    }
+}
@@ -0,0 +1,180 @@
+//! What can be told about a recording from its name and metadata
+
+use std::{fs, io, path::Path};
+
+use chrono::{DateTime, Local, NaiveDateTime, Utc};
+
+use crate::mp4_file;
+
+/// Seconds between the mp4 epoch (1904) and the unix epoch
+const MP4_EPOCH_OFFSET: i64 = 2_082_844_800;
+
+/// The lens of a multi-channel dashcam
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Channel {
+    Front,
+    Rear,
+}
+
+/// Where the recording time was taken from, from the most to the least reliable
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimestampSource {
+    Filename,
+    Metadata,
+    Modified,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClipInfo {
+    /// The wall-clock time of the camera, dashcams don't record a timezone
+    pub recorded_at: NaiveDateTime,
+    pub source: TimestampSource,
+    pub channel: Option<Channel>,
+    /// The running counter of the camera, if it's part of the name
+    pub sequence: Option<u32>,
+}
+
+impl ClipInfo {
+    /// Reads the clip's name, falling back to the mp4 creation time and then to the mtime
+    pub fn read(path: &Path, metadata: &fs::Metadata) -> io::Result<Self> {
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        if let Some(clip) = Self::from_name(&name) {
+            return Ok(clip);
+        }
+
+        if let Some(recorded_at) = mp4_creation_time(path) {
+            return Ok(Self {
+                recorded_at,
+                source: TimestampSource::Metadata,
+                channel: None,
+                sequence: None,
+            });
+        }
+
+        Ok(Self {
+            recorded_at: DateTime::<Local>::from(metadata.modified()?).naive_local(),
+            source: TimestampSource::Modified,
+            channel: None,
+            sequence: None,
+        })
+    }
+
+    /// Whether the two clips were recorded at the same moment by the two lenses of a camera
+    pub fn pairs_with(&self, other: &ClipInfo) -> bool {
+        self.source == TimestampSource::Filename
+            && other.source == TimestampSource::Filename
+            && self.recorded_at == other.recorded_at
+            && matches!(
+                (self.channel, other.channel),
+                (Some(Channel::Front), Some(Channel::Rear))
+                    | (Some(Channel::Rear), Some(Channel::Front))
+            )
+    }
+
+    /// Parses the common dashcam naming schemes, e.g.
+    /// `2024_0326_153012_001F.MP4`, `20240326153012_000123.MP4`,
+    /// `FILE240326-153012F.MP4` and `NO20240326-153012-000123F.MP4`
+    pub fn from_name(name: &str) -> Option<Self> {
+        let stem = name.split('.').next()?.to_uppercase();
+        // e.g. FILE, or NO/EV/PA for the recording mode
+        let stem = stem.trim_start_matches(|c: char| c.is_ascii_alphabetic());
+        let (stem, channel) = match stem.trim_end_matches(|c: char| c.is_ascii_alphabetic()) {
+            rest if rest.len() < stem.len() => {
+                let channel = match stem.chars().last() {
+                    Some('F') => Some(Channel::Front),
+                    Some('R') => Some(Channel::Rear),
+                    _ => None,
+                };
+                (rest.trim_end_matches(['_', '-']), channel)
+            }
+            rest => (rest, None),
+        };
+
+        if !stem
+            .chars()
+            .all(|c| c.is_ascii_digit() || c == '_' || c == '-')
+        {
+            return None;
+        }
+        let mut groups = stem.split(['_', '-']);
+
+        // the date and time may be split into several groups
+        let mut digits = String::new();
+        let format = loop {
+            digits.push_str(groups.next()?);
+            match digits.len() {
+                12 => break "%y%m%d%H%M%S",
+                14 => break "%Y%m%d%H%M%S",
+                len if len > 14 => return None,
+                _ => {}
+            }
+        };
+        let recorded_at = NaiveDateTime::parse_from_str(&digits, format).ok()?;
+        let sequence = match (groups.next(), groups.next()) {
+            (None, _) => None,
+            (Some(sequence), None) => Some(sequence.parse().ok()?),
+            (Some(_), Some(_)) => return None,
+        };
+
+        Some(Self {
+            recorded_at,
+            source: TimestampSource::Filename,
+            channel,
+            sequence,
+        })
+    }
+}
+
+/// The creation time in the mvhd box, which dashcams set to their local time
+fn mp4_creation_time(path: &Path) -> Option<NaiveDateTime> {
+    let mp4 = mp4_file::open(path).ok()?;
+
+    let seconds = i64::try_from(mp4.moov.mvhd.creation_time).ok()? - MP4_EPOCH_OFFSET;
+    // unset, or a camera whose clock was never set
+    if seconds <= 0 {
+        return None;
+    }
+
+    DateTime::<Utc>::from_timestamp(seconds, 0).map(|time| time.naive_utc())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(name: &str) -> Option<(String, Option<Channel>, Option<u32>)> {
+        ClipInfo::from_name(name).map(|clip| {
+            (
+                clip.recorded_at.format("%Y-%m-%d %H:%M:%S").to_string(),
+                clip.channel,
+                clip.sequence,
+            )
+        })
+    }
+
+    #[test]
+    fn parse_dashcam_names() {
+        let time = "2024-03-26 15:30:12".to_string();
+
+        assert_eq!(
+            parse("2024_0326_153012_001F.MP4"),
+            Some((time.clone(), Some(Channel::Front), Some(1)))
+        );
+        assert_eq!(
+            parse("20240326153012_000123.MP4"),
+            Some((time.clone(), None, Some(123)))
+        );
+        assert_eq!(
+            parse("FILE240326-153012R.MP4"),
+            Some((time.clone(), Some(Channel::Rear), None))
+        );
+        assert_eq!(
+            parse("NO20240326-153012-000123F.MP4"),
+            Some((time.clone(), Some(Channel::Front), Some(123)))
+        );
+
+        assert_eq!(parse("MOVI0001.MP4"), None);
+        assert_eq!(parse("2024_1326_153012_001F.MP4"), None);
+    }
+}
@@ -0,0 +1,77 @@
+//! Running the external tools the user configured (e.g. ffmpeg)
+
+use std::{
+    ffi::{OsStr, OsString},
+    io,
+    process::{Command, Stdio},
+    thread,
+    time::{Duration, Instant},
+};
+
+/// Runs a command template, killing it if it takes longer than `timeout`
+///
+/// the template is split on whitespace (there is no quoting), and every
+/// `{name}` in it is replaced by the value of the matching variable.
+pub fn run(template: &str, vars: &[(&str, &OsStr)], timeout: Duration) -> io::Result<()> {
+    let mut args = template.split_whitespace().map(|arg| expand(arg, vars));
+    let program = args
+        .next()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "the command is empty"))?;
+
+    let mut child = Command::new(program)
+        .args(args)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()?;
+    let started = Instant::now();
+    let status = loop {
+        if let Some(status) = child.try_wait()? {
+            break status;
+        }
+        if started.elapsed() > timeout {
+            let _ = child.kill();
+            let _ = child.wait();
+            return Err(io::ErrorKind::TimedOut.into());
+        }
+        thread::sleep(Duration::from_millis(100));
+    };
+
+    if !status.success() {
+        return Err(io::Error::other(format!("exited with {status}")));
+    }
+    Ok(())
+}
+
+fn expand(arg: &str, vars: &[(&str, &OsStr)]) -> OsString {
+    // paths are passed as they are when they make up the whole argument,
+    // as they may not be valid unicode
+    if let Some((_, value)) = vars
+        .iter()
+        .find(|(name, _)| arg.strip_prefix('{').and_then(|arg| arg.strip_suffix('}')) == Some(name))
+    {
+        return value.into();
+    }
+
+    vars.iter()
+        .fold(arg.to_string(), |arg, (name, value)| {
+            arg.replace(&format!("{{{name}}}"), &value.to_string_lossy())
+        })
+        .into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn expand_variables() {
+        let vars: &[(&str, &OsStr)] =
+            &[("input", "a b.mp4".as_ref()), ("bitrate", "4000".as_ref())];
+
+        assert_eq!(expand("{input}", vars), "a b.mp4");
+        assert_eq!(expand("{bitrate}k", vars), "4000k");
+        assert_eq!(expand("{unknown}", vars), "{unknown}");
+        assert_eq!(expand("-y", vars), "-y");
+    }
+}
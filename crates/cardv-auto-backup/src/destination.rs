@@ -0,0 +1,145 @@
+//! Where the recordings are backed up to
+
+use std::{fmt::Debug, future::Future, io, path::PathBuf};
+
+use crate::{settings::Settings, split::SplitErr, video::VideoInfo};
+
+#[derive(thiserror::Error, Debug)]
+pub enum DestinationErr {
+    #[error("{0}")]
+    Io(#[from] io::Error),
+
+    #[error("failed to split the oversized file: {0}")]
+    Split(#[from] SplitErr),
+
+    #[error("the request has timed out")]
+    Timeout,
+
+    /// An error of the destination itself, which knows how serious it is
+    #[error("{source}")]
+    Backend {
+        class: ErrorClass,
+        source: Box<dyn std::error::Error + Send + Sync>,
+    },
+}
+
+impl DestinationErr {
+    pub fn class(&self) -> ErrorClass {
+        match self {
+            Self::Io(_) | Self::Split(_) => ErrorClass::Skippable,
+            Self::Timeout => ErrorClass::Retryable,
+            Self::Backend { class, .. } => *class,
+        }
+    }
+}
+
+/// How the uploader should react to an error
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorClass {
+    /// A temporary failure (e.g. network issues), the same request may succeed later
+    Retryable,
+    /// Something is wrong with the file itself, the rest can still be uploaded
+    Skippable,
+    /// The destination can no longer be uploaded to (e.g. its authorization was revoked)
+    Fatal,
+}
+
+/// Changes in the health of the connection to the destination
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConnectionEvent {
+    /// The connection was found dead, and we're trying to reconnect
+    Lost,
+    Restored,
+}
+
+/// How an uploaded file is presented
+#[derive(Debug, Clone, Copy)]
+pub enum MediaKind<'a> {
+    /// A playable mp4, with a JPEG thumbnail (see [`crate::thumbnail`])
+    Video {
+        thumbnail: Option<&'a [u8]>,
+    },
+    Photo,
+    /// A plain file, e.g. a clip that couldn't be repaired
+    Document,
+}
+
+/// A place the uploader sends the recordings to, e.g. a telegram channel
+///
+/// files are uploaded and published in two steps, so the (slow) uploads can
+/// run concurrently, while the entries themselves are still published in order.
+/// every published entry is identified by an id, which replies refer to and the
+/// ledger keeps.
+pub trait Destination: Debug + Send + Sync + 'static {
+    /// A file that was uploaded, but isn't published yet
+    type Media: Send + Sync + 'static;
+
+    /// What is known about an uploaded video, which the caption is rendered from
+    fn video_info(media: &Self::Media) -> Option<&VideoInfo>;
+
+    /// The biggest file that can be uploaded in one piece, bigger videos are split
+    fn max_file_size(&self, settings: &Settings) -> u64;
+
+    /// The longest caption a message can have, in characters
+    fn max_caption_len(&self) -> usize;
+
+    /// Mark the connection as suspect, so it's checked before the next request
+    fn connection_failed(&self);
+
+    /// Makes sure the destination can be reached, reconnecting if needed
+    fn ensure_connected(
+        &self,
+        on_event: impl Fn(ConnectionEvent) + Send,
+    ) -> impl Future<Output = Result<(), DestinationErr>> + Send;
+
+    /// Uploads a file, without publishing it yet
+    ///
+    /// `on_progress` is called with the amount of bytes sent so far,
+    /// and starts over from zero if the upload has to be restarted.
+    fn upload(
+        &self,
+        path: PathBuf,
+        kind: MediaKind<'_>,
+        on_progress: impl Fn(u64) + Send + Sync,
+    ) -> impl Future<Output = Result<Self::Media, DestinationErr>> + Send;
+
+    /// Publishes uploaded media with its caption, returning the id of the entry
+    fn send(
+        &self,
+        media: &Self::Media,
+        caption: String,
+        reply_to: Option<i32>,
+    ) -> impl Future<Output = Result<i32, DestinationErr>> + Send;
+
+    /// Publishes uploaded media as a single entry, with the caption on the first item
+    ///
+    /// the ids are in the order of the media, None if one wasn't published.
+    fn send_album(
+        &self,
+        media: &[&Self::Media],
+        caption: String,
+    ) -> impl Future<Output = Result<Vec<Option<i32>>, DestinationErr>> + Send;
+
+    /// Publishes a small file (e.g. a GPX track)
+    fn send_document(
+        &self,
+        name: String,
+        mime_type: &str,
+        bytes: &[u8],
+        reply_to: Option<i32>,
+    ) -> impl Future<Output = Result<i32, DestinationErr>> + Send;
+
+    /// Publishes a location pin
+    fn send_location(
+        &self,
+        latitude: f64,
+        longitude: f64,
+        reply_to: Option<i32>,
+    ) -> impl Future<Output = Result<(), DestinationErr>> + Send;
+
+    /// Whether an entry is still there, it may have been deleted by hand
+    fn exists(&self, id: i32) -> impl Future<Output = Result<bool, DestinationErr>> + Send;
+
+    /// Removes published entries
+    fn delete(&self, ids: &[i32]) -> impl Future<Output = Result<(), DestinationErr>> + Send;
+}
@@ -1,5 +1,6 @@
 use std::marker::PhantomData;
 
+#[cfg(windows)]
 use windows::Win32::System::Power::{
     SetThreadExecutionState, ES_AWAYMODE_REQUIRED, ES_CONTINUOUS, ES_SYSTEM_REQUIRED,
     EXECUTION_STATE,
@@ -7,11 +8,15 @@ use windows::Win32::System::Power::{
 
 /// Execution state is a RAII wrapper for 'SetThreadExecutionState'
 /// that automatically resets the state when dropped
+///
+/// on other platforms this is a no-op, it's up to the
+/// system configuration to keep the machine awake.
 #[derive(Debug)]
 pub struct ExecutionState {
     _force_constructor_usage: PhantomData<()>,
 }
 
+#[cfg(windows)]
 impl ExecutionState {
     pub fn new(flags: EXECUTION_STATE) -> Self {
         unsafe { SetThreadExecutionState(flags) };
@@ -27,9 +32,19 @@ impl ExecutionState {
     }
 }
 
+#[cfg(windows)]
 impl Drop for ExecutionState {
     fn drop(&mut self) {
         // Safety: !
         unsafe { SetThreadExecutionState(ES_CONTINUOUS) };
     }
 }
+
+#[cfg(not(windows))]
+impl ExecutionState {
+    pub fn away_system() -> Self {
+        Self {
+            _force_constructor_usage: PhantomData,
+        }
+    }
+}
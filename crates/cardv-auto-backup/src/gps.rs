@@ -0,0 +1,399 @@
+//! GPS tracks embedded by dashcams in their recordings
+//!
+//! two ways of embedding them are understood:
+//! - the novatek `gps ` atom in the moov box, an index of `free` boxes with a binary fix each
+//! - subtitle tracks carrying NMEA `RMC` sentences
+//!
+//! the novatek boxes are also found without their index, by scanning the
+//! file for them, as clips cut short by a power loss have no moov box.
+
+use std::{
+    fmt::Write as _,
+    fs::File,
+    io::{self, BufReader, Read, Seek, SeekFrom},
+    path::Path,
+};
+
+use chrono::{NaiveDate, NaiveDateTime, NaiveTime};
+use mp4::TrackType;
+
+pub const GPX_MIME_TYPE: &str = "application/gpx+xml";
+
+const KNOTS_TO_KMH: f64 = 1.852;
+/// Anything bigger isn't a GPS block, but a corrupted index
+const MAX_BLOCK_SIZE: u64 = 64 * 1024;
+/// How the novatek blocks start, right after their size
+const BLOCK_MAGIC: &[u8; 8] = b"freeGPS ";
+/// How much of the file is read at a time when scanning for blocks
+const SCAN_CHUNK: usize = 1024 * 1024;
+/// The blocks are written every second, a file without any this far in has none at all
+const SCAN_PROBE: u64 = 32 * 1024 * 1024;
+
+/// A single fix of the GPS receiver
+#[derive(Debug, Clone, PartialEq)]
+pub struct GpsPoint {
+    /// GPS time, which is UTC
+    pub time: NaiveDateTime,
+    pub latitude: f64,
+    pub longitude: f64,
+    /// In km/h
+    pub speed: Option<f64>,
+    /// In degrees, clockwise from north
+    pub course: Option<f64>,
+}
+
+/// Reads the GPS track of a clip, empty if the camera recorded none
+pub fn extract(path: &Path) -> io::Result<Vec<GpsPoint>> {
+    let mut file = BufReader::new(File::open(path)?);
+    let mut points = novatek_points(&mut file)?;
+
+    if points.is_empty() {
+        let size = file.get_ref().metadata()?.len();
+        file.rewind()?;
+        points = subtitle_points(&mut file, size).unwrap_or_default();
+    }
+    if points.is_empty() {
+        points = scanned_points(&mut file)?;
+        if points.is_empty() {
+            tracing::debug!(?path, "found no GPS data, skipping the track");
+        }
+    }
+
+    // receivers repeat the last fix while they have no new one
+    points.dedup_by(|next, prev| next.time == prev.time);
+    Ok(points)
+}
+
+/// Writes the track as a GPX 1.0 file, which still has speed and course
+pub fn to_gpx(name: &str, points: &[GpsPoint]) -> String {
+    let name = name
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;");
+
+    let mut gpx = String::new();
+    gpx.push_str("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    gpx.push_str("<gpx version=\"1.0\" creator=\"cardv-auto-backup\" xmlns=\"http://www.topografix.com/GPX/1/0\">\n");
+    let _ = writeln!(gpx, "  <trk>\n    <name>{name}</name>\n    <trkseg>");
+    for point in points {
+        let _ = write!(
+            gpx,
+            "      <trkpt lat=\"{:.6}\" lon=\"{:.6}\"><time>{}</time>",
+            point.latitude,
+            point.longitude,
+            point.time.format("%Y-%m-%dT%H:%M:%SZ")
+        );
+        if let Some(course) = point.course {
+            let _ = write!(gpx, "<course>{course:.1}</course>");
+        }
+        if let Some(speed) = point.speed {
+            // m/s
+            let _ = write!(gpx, "<speed>{:.2}</speed>", speed / 3.6);
+        }
+        gpx.push_str("</trkpt>\n");
+    }
+    gpx.push_str("    </trkseg>\n  </trk>\n</gpx>\n");
+
+    gpx
+}
+
+fn novatek_points(file: &mut (impl Read + Seek)) -> io::Result<Vec<GpsPoint>> {
+    let Some((moov, moov_size)) = find_box(file, 0, u64::MAX, b"moov")? else {
+        return Ok(vec![]);
+    };
+    let Some((gps, gps_size)) = find_box(file, moov, moov + moov_size, b"gps ")? else {
+        return Ok(vec![]);
+    };
+
+    // a version and a date follow the header, then the (offset, size) of every block
+    file.seek(SeekFrom::Start(gps + 16))?;
+    let mut index = vec![0; gps_size.saturating_sub(16).min(MAX_BLOCK_SIZE) as usize];
+    file.read_exact(&mut index)?;
+
+    let mut points = vec![];
+    for entry in index.chunks_exact(8) {
+        let offset = u32::from_be_bytes(entry[..4].try_into().unwrap());
+        let size = u32::from_be_bytes(entry[4..].try_into().unwrap());
+        if offset == 0 || size == 0 || u64::from(size) > MAX_BLOCK_SIZE {
+            continue;
+        }
+
+        let mut block = vec![0; size as usize];
+        file.seek(SeekFrom::Start(offset.into()))?;
+        if file.read_exact(&mut block).is_err() {
+            // a truncated recording
+            break;
+        }
+        if block.get(4..12) == Some(BLOCK_MAGIC) {
+            points.extend(novatek_fix(&block[12..]));
+        }
+    }
+
+    Ok(points)
+}
+
+/// Finds the novatek blocks by their magic, for when their index is missing
+fn scanned_points(file: &mut (impl Read + Seek)) -> io::Result<Vec<GpsPoint>> {
+    file.rewind()?;
+    let mut offsets = vec![];
+    // the tail of the previous chunk is kept, for the blocks that straddle two chunks
+    let mut window = vec![];
+    // where the window starts in the file
+    let mut start = 0;
+    let mut chunk = vec![0; SCAN_CHUNK];
+    loop {
+        let read = file.read(&mut chunk)?;
+        if read == 0 || (offsets.is_empty() && start > SCAN_PROBE) {
+            break;
+        }
+        window.extend_from_slice(&chunk[..read]);
+
+        // the size of a block comes before the magic
+        offsets.extend(
+            window
+                .windows(BLOCK_MAGIC.len())
+                .enumerate()
+                .filter(|(at, bytes)| *at >= 4 && bytes == BLOCK_MAGIC)
+                .map(|(at, _)| start + at as u64 - 4),
+        );
+        let keep = window.len().min(BLOCK_MAGIC.len() + 3);
+        start += (window.len() - keep) as u64;
+        window.drain(..window.len() - keep);
+    }
+
+    let mut points = vec![];
+    for offset in offsets {
+        let mut size = [0; 4];
+        file.seek(SeekFrom::Start(offset))?;
+        file.read_exact(&mut size)?;
+        let size = u32::from_be_bytes(size);
+        if !(12..=MAX_BLOCK_SIZE).contains(&size.into()) {
+            // the magic happened to be in the video
+            continue;
+        }
+
+        let mut block = vec![0; size as usize];
+        file.seek(SeekFrom::Start(offset))?;
+        if file.read_exact(&mut block).is_err() {
+            break;
+        }
+        points.extend(novatek_fix(&block[12..]));
+    }
+
+    Ok(points)
+}
+
+/// Finds a box between `start` and `end`, returning where it starts and its size
+fn find_box(
+    file: &mut (impl Read + Seek),
+    start: u64,
+    end: u64,
+    name: &[u8; 4],
+) -> io::Result<Option<(u64, u64)>> {
+    // the children of a box start after its header
+    let mut offset = if start == 0 { 0 } else { start + 8 };
+    while offset < end {
+        file.seek(SeekFrom::Start(offset))?;
+        let mut header = [0; 8];
+        if file.read_exact(&mut header).is_err() {
+            return Ok(None);
+        }
+
+        let size = match u32::from_be_bytes(header[..4].try_into().unwrap()) {
+            // the box extends to the end of the file
+            0 => return Ok((&header[4..] == name).then_some((offset, end - offset))),
+            1 => {
+                let mut size = [0; 8];
+                file.read_exact(&mut size)?;
+                u64::from_be_bytes(size)
+            }
+            size => size.into(),
+        };
+        if &header[4..] == name {
+            return Ok(Some((offset, size)));
+        }
+        if size < 8 {
+            return Ok(None);
+        }
+        offset += size;
+    }
+
+    Ok(None)
+}
+
+/// Decodes a novatek fix
+///
+/// the layout differs between firmwares by some unknown fields in front of the fix,
+/// so it's searched for: hour, minute, second, year, month and day as u32s, the
+/// status, latitude and longitude hemispheres, a padding byte, and then the
+/// latitude, longitude, speed (in knots) and course as f32s, all little-endian.
+fn novatek_fix(payload: &[u8]) -> Option<GpsPoint> {
+    let u32_at = |at: usize| u32::from_le_bytes(payload[at..at + 4].try_into().unwrap());
+    let f32_at = |at: usize| f32::from_le_bytes(payload[at..at + 4].try_into().unwrap()) as f64;
+
+    (0..=payload.len().checked_sub(44)?)
+        .step_by(4)
+        .find_map(|at| {
+            let (status, lat_hemisphere, lon_hemisphere) =
+                (payload[at + 24], payload[at + 25], payload[at + 26]);
+            if status != b'A'
+                || !matches!(lat_hemisphere, b'N' | b'S')
+                || !matches!(lon_hemisphere, b'E' | b'W')
+            {
+                return None;
+            }
+
+            let year = match u32_at(at + 12) {
+                year @ 0..=99 => year + 2000,
+                year => year,
+            };
+            let date = NaiveDate::from_ymd_opt(year as i32, u32_at(at + 16), u32_at(at + 20))?;
+            let time = NaiveTime::from_hms_opt(u32_at(at), u32_at(at + 4), u32_at(at + 8))?;
+
+            Some(GpsPoint {
+                time: date.and_time(time),
+                latitude: signed(nmea_degrees(f32_at(at + 28)), lat_hemisphere == b'S'),
+                longitude: signed(nmea_degrees(f32_at(at + 32)), lon_hemisphere == b'W'),
+                speed: Some(f32_at(at + 36) * KNOTS_TO_KMH),
+                course: Some(f32_at(at + 40)),
+            })
+        })
+}
+
+fn subtitle_points(file: impl Read + Seek, size: u64) -> mp4::Result<Vec<GpsPoint>> {
+    let mut mp4 = mp4::Mp4Reader::read_header(file, size)?;
+    let tracks = mp4
+        .tracks()
+        .iter()
+        .filter(|(_, track)| track.track_type().ok() == Some(TrackType::Subtitle))
+        .map(|(id, _)| *id)
+        .collect::<Vec<_>>();
+
+    let mut points = vec![];
+    for id in tracks {
+        for sample in 1..=mp4.sample_count(id)? {
+            let Some(sample) = mp4.read_sample(id, sample)? else {
+                continue;
+            };
+            // tx3g samples start with the length of the text
+            let text = sample.bytes.get(2..).unwrap_or_default();
+            points.extend(String::from_utf8_lossy(text).lines().filter_map(parse_rmc));
+        }
+    }
+
+    Ok(points)
+}
+
+/// Parses a `$GPRMC` (or any other talker's) sentence, e.g.
+/// `$GPRMC,153012.00,A,5546.1234,N,03736.5678,E,12.3,45.6,260324,,,A*6C`
+fn parse_rmc(sentence: &str) -> Option<GpsPoint> {
+    let sentence = sentence.trim().strip_prefix('$')?;
+    let sentence = sentence.split('*').next()?;
+    let fields = sentence.split(',').collect::<Vec<_>>();
+    if !fields.first()?.ends_with("RMC") || fields.len() < 10 || fields[2] != "A" {
+        return None;
+    }
+
+    let time = NaiveTime::parse_from_str(fields[1].split('.').next()?, "%H%M%S").ok()?;
+    let date = NaiveDate::parse_from_str(fields[9], "%d%m%y").ok()?;
+
+    Some(GpsPoint {
+        time: date.and_time(time),
+        latitude: signed(nmea_degrees(fields[3].parse().ok()?), fields[4] == "S"),
+        longitude: signed(nmea_degrees(fields[5].parse().ok()?), fields[6] == "W"),
+        speed: fields[7]
+            .parse::<f64>()
+            .ok()
+            .map(|knots| knots * KNOTS_TO_KMH),
+        course: fields[8].parse().ok(),
+    })
+}
+
+/// Converts the NMEA `dddmm.mmmm` format into degrees
+fn nmea_degrees(value: f64) -> f64 {
+    let degrees = (value / 100.0).trunc();
+    degrees + (value - degrees * 100.0) / 60.0
+}
+
+fn signed(degrees: f64, negative: bool) -> f64 {
+    if negative {
+        -degrees
+    } else {
+        degrees
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+    /// A novatek fix at 15:30 of 2024-03-26, with some unknown fields in front of it
+    fn novatek_payload(second: u32) -> Vec<u8> {
+        let mut payload = vec![0; 36];
+        for value in [15u32, 30, second, 24, 3, 26] {
+            payload.extend(value.to_le_bytes());
+        }
+        payload.extend(b"ANE\0");
+        for value in [5546.1234f32, 3736.5678, 10.0, 45.6] {
+            payload.extend(value.to_le_bytes());
+        }
+        payload
+    }
+
+    #[test]
+    fn decode_gps_fixes() {
+        let time =
+            NaiveDateTime::parse_from_str("2024-03-26 15:30:12", "%Y-%m-%d %H:%M:%S").unwrap();
+
+        let point =
+            parse_rmc("$GPRMC,153012.00,A,5546.1234,S,03736.5678,E,10.0,45.6,260324,,,A*6C")
+                .unwrap();
+        assert_eq!(point.time, time);
+        assert!((point.latitude + 55.768723).abs() < 1e-6);
+        assert!((point.longitude - 37.609463).abs() < 1e-6);
+        assert!((point.speed.unwrap() - 18.52).abs() < 1e-6);
+        assert_eq!(parse_rmc("$GPRMC,153012.00,V,,,,,,,260324,,,N*6C"), None);
+
+        let point = novatek_fix(&novatek_payload(12)).unwrap();
+        assert_eq!(point.time, time);
+        assert!((point.latitude - 55.768723).abs() < 1e-4);
+        assert!((point.longitude - 37.609463).abs() < 1e-4);
+
+        let gpx = to_gpx("A & B", &[point]);
+        assert!(gpx.contains("<name>A &amp; B</name>"));
+        assert!(gpx.contains("<time>2024-03-26T15:30:12Z</time>"));
+    }
+
+    #[test]
+    fn scan_blocks_without_index() {
+        let block = |second| {
+            let payload = novatek_payload(second);
+            let mut block = (payload.len() as u32 + 12).to_be_bytes().to_vec();
+            block.extend(BLOCK_MAGIC);
+            block.extend(payload);
+            block
+        };
+
+        let mut file = vec![0x42; 1000];
+        file.extend(block(12));
+        // one that straddles two chunks
+        file.resize(SCAN_CHUNK - 10, 0x42);
+        file.extend(block(13));
+        // the magic in the middle of a frame, without a sensible size in front of it
+        file.extend([0xFF; 4]);
+        file.extend(BLOCK_MAGIC);
+        file.resize(2 * SCAN_CHUNK, 0x42);
+        file.extend(block(14));
+
+        let points = scanned_points(&mut Cursor::new(file)).unwrap();
+        assert_eq!(
+            points
+                .iter()
+                .map(|point| point.time.format("%H:%M:%S").to_string())
+                .collect::<Vec<_>>(),
+            ["15:30:12", "15:30:13", "15:30:14"]
+        );
+    }
+}
@@ -90,7 +90,7 @@ impl eframe::App for App {
                     self.state = State::Uploader(Uploader::new(bot, frame.storage()));
                 }
             }
-            State::Uploader(uploader) => uploader.show(ctx, frame),
+            State::Uploader(uploader) => uploader.show(ctx, frame.storage_mut()),
         }
     }
 }
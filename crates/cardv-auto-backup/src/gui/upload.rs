@@ -1,18 +1,45 @@
+use std::{
+    collections::BTreeMap,
+    path::PathBuf,
+    time::{Duration, Instant},
+};
+
 use eframe::Storage;
-use egui::{Color32, ProgressBar, RichText, Spinner};
+use egui::{Color32, ProgressBar, RichText, Spinner, TextEdit};
+use tokio::sync::watch;
 
 use crate::{
+    caption::{self, format_bytes, format_duration, CaptionInfo},
+    destination::ConnectionEvent,
     execution_state::ExecutionState,
+    layout::Category,
+    queue::UploadOrder,
+    settings::Settings,
     tg::Bot,
-    usb::{DriveUploader, UploaderMsg},
+    thumbnail,
+    transcode::{self, Preset},
+    usb::{DriveUploader, OsDriveSource, Summary, UploaderMsg},
 };
 
+const LEDGER_FILE: &str = "ledger.jsonl";
+/// The last uploaded file, as kept before the ledger existed
 const LAST_UPLOAD_STORAGE_KEY: &str = "LAST_UPLOAD";
 
+/// The most files the settings allow to upload at the same time
+const MAX_CONCURRENT_UPLOADS: usize = 8;
+
+/// How often the throughput is sampled
+const SAMPLE_INTERVAL: Duration = Duration::from_secs(1);
+/// How much weight a new throughput sample gets
+const SMOOTHING: f64 = 0.3;
+
 #[derive(Debug)]
 pub struct Uploader {
     uploader: DriveUploader,
     state: State,
+    settings: Settings,
+    // the uploader picks up the latest settings once a drive is inserted
+    settings_tx: watch::Sender<Settings>,
 }
 
 #[derive(Debug)]
@@ -20,86 +47,222 @@ enum State {
     Error(String),
     WaitForDrive,
     Uploading(UploadingState),
-    Finished,
+    Finished(Summary),
 }
 
 #[derive(Debug)]
 struct UploadingState {
-    current_name: Option<String>,
     current: usize,
     total: usize,
+    /// The files that are being uploaded, by their position in the backup
+    active: BTreeMap<usize, ActiveFile>,
+    done_bytes: u64,
+    total_bytes: u64,
+    throughput: Throughput,
+    connection_lost: bool,
+    reconnects: usize,
     // prevent the computer from going
     // to sleep while we upload the files
     _exec_state: ExecutionState,
 }
 
+#[derive(Debug)]
+struct ActiveFile {
+    name: String,
+    sent: u64,
+    size: u64,
+}
+
 impl UploadingState {
+    fn total_sent(&self) -> u64 {
+        self.done_bytes + self.active.values().map(|file| file.sent).sum::<u64>()
+    }
+
     fn show(&self, ctx: &egui::Context) {
         egui::CentralPanel::default().show(ctx, |ui| {
             ui.vertical_centered(|ui| {
                 ui.heading(format!("Uploading {} files", self.total));
                 ui.spinner();
 
-                let text = match &self.current_name {
-                    Some(name) => format!("{} - {}/{}", name, self.current, self.total),
-                    None => format!("{}/{}", self.current, self.total),
-                };
-
                 ui.add(
                     ProgressBar::new((self.current as f32) / (self.total as f32))
-                        .text(text)
+                        .text(format!("{}/{}", self.current, self.total))
                         .fill(Color32::GREEN),
                 );
+
+                for file in self.active.values() {
+                    ui.add(
+                        ProgressBar::new(file.sent as f32 / file.size.max(1) as f32).text(format!(
+                            "{} - {} / {}",
+                            file.name,
+                            format_bytes(file.sent),
+                            format_bytes(file.size)
+                        )),
+                    );
+                }
+
+                let total_sent = self.total_sent();
+                let overall = format!(
+                    "{} / {} overall",
+                    format_bytes(total_sent),
+                    format_bytes(self.total_bytes)
+                );
+                match self.throughput.rate {
+                    Some(rate) if rate > 0.0 => {
+                        let left = self.total_bytes.saturating_sub(total_sent);
+                        let eta = Duration::from_secs_f64(left as f64 / rate);
+                        ui.label(format!(
+                            "{overall} - {}/s, {} left",
+                            format_bytes(rate as u64),
+                            format_duration(eta)
+                        ));
+                    }
+                    _ => {
+                        ui.label(overall);
+                    }
+                }
+
+                if self.connection_lost {
+                    ui.label(
+                        RichText::new("connection lost, reconnecting...").color(Color32::YELLOW),
+                    );
+                } else if self.reconnects > 0 {
+                    ui.label(format!("reconnected {} times", self.reconnects));
+                }
             });
         });
     }
 }
 
+/// Smoothed upload speed, in bytes per second
+#[derive(Debug, Default)]
+struct Throughput {
+    last_sample: Option<(Instant, u64)>,
+    rate: Option<f64>,
+}
+
+impl Throughput {
+    fn update(&mut self, total_sent: u64) {
+        let now = Instant::now();
+        let Some((sampled_at, sent)) = self.last_sample else {
+            self.last_sample = Some((now, total_sent));
+            return;
+        };
+
+        let elapsed = now.duration_since(sampled_at);
+        if elapsed < SAMPLE_INTERVAL {
+            return;
+        }
+
+        // a restarted upload goes backwards, which doesn't count as negative speed
+        let sample = total_sent.saturating_sub(sent) as f64 / elapsed.as_secs_f64();
+        self.rate = Some(match self.rate {
+            Some(rate) => rate * (1.0 - SMOOTHING) + sample * SMOOTHING,
+            None => sample,
+        });
+        self.last_sample = Some((now, total_sent));
+    }
+}
+
 impl Uploader {
     pub fn new(bot: Bot, storage: Option<&dyn Storage>) -> Self {
+        let settings = Settings::load(storage);
+        let (settings_tx, settings_rx) = watch::channel(settings.clone());
+        // the storage can't remove keys, so an emptied key counts as a missing one
+        let last_upload = storage
+            .and_then(|storage| storage.get_string(LAST_UPLOAD_STORAGE_KEY))
+            .filter(|last_upload| !last_upload.is_empty())
+            .map(PathBuf::from);
+
         Self {
             uploader: DriveUploader::new(
                 bot,
-                storage.and_then(|storage| storage.get_string(LAST_UPLOAD_STORAGE_KEY)),
+                OsDriveSource,
+                settings_rx,
+                eframe::storage_dir(crate::APP_ID)
+                    .unwrap_or_default()
+                    .join(LEDGER_FILE),
+                last_upload,
             ),
             state: State::WaitForDrive,
+            settings,
+            settings_tx,
         }
     }
 
-    pub fn show(&mut self, ctx: &egui::Context, frame: &mut eframe::Frame) {
+    pub fn show(&mut self, ctx: &egui::Context, mut storage: Option<&mut dyn Storage>) {
         match &self.state {
-            State::WaitForDrive => self.wait_for_drive(ctx),
+            State::WaitForDrive => self.wait_for_drive(ctx, storage.as_deref_mut()),
             State::Uploading(uploading) => uploading.show(ctx),
             State::Error(reason) => Self::error(reason, ctx),
-            State::Finished => Self::finished(ctx),
+            State::Finished(summary) => Self::finished(summary, ctx),
         }
 
-        if let Some(msg) = self.uploader.try_recv() {
+        // progress is reported often, so drain everything that has piled up
+        while let Some(msg) = self.uploader.try_recv() {
             match (&mut self.state, msg) {
-                (State::WaitForDrive, UploaderMsg::Start(total)) => {
+                (State::WaitForDrive, UploaderMsg::Start { files, bytes }) => {
+                    // the drive was scanned, so the ledger has taken over from the last upload
+                    if let Some(storage) = storage.as_deref_mut() {
+                        storage.set_string(LAST_UPLOAD_STORAGE_KEY, String::new());
+                    }
                     self.state = State::Uploading(UploadingState {
-                        current_name: None,
                         current: 0,
-                        total,
+                        total: files,
+                        active: BTreeMap::new(),
+                        done_bytes: 0,
+                        total_bytes: bytes,
+                        throughput: Throughput::default(),
+                        connection_lost: false,
+                        reconnects: 0,
                         _exec_state: ExecutionState::away_system(),
                     })
                 }
                 (State::Uploading(uploading), UploaderMsg::Update(update)) => {
-                    uploading.current_name = Some(update.uploading);
-                    uploading.current = update.current;
-                }
-                (State::Uploading(_), UploaderMsg::Uploaded(path)) => {
-                    if let Some(storage) = frame.storage_mut() {
-                        storage.set_string(
-                            LAST_UPLOAD_STORAGE_KEY,
-                            path.to_string_lossy().to_string(),
-                        );
+                    let previous = uploading.active.insert(
+                        update.current,
+                        ActiveFile {
+                            name: update.uploading,
+                            sent: 0,
+                            size: update.size,
+                        },
+                    );
+                    // the file was replaced, e.g. by a transcoded copy
+                    if let Some(previous) = previous {
+                        uploading.total_bytes =
+                            (uploading.total_bytes + update.size).saturating_sub(previous.size);
+                    }
+                }
+                (State::Uploading(uploading), UploaderMsg::Progress(progress)) => {
+                    if let Some(file) = uploading.active.get_mut(&progress.current) {
+                        file.sent = progress.sent;
+                    }
+                    let total_sent = uploading.total_sent();
+                    uploading.throughput.update(total_sent);
+                }
+                (State::Uploading(uploading), UploaderMsg::Completed(current)) => {
+                    if let Some(file) = uploading.active.remove(&current) {
+                        uploading.done_bytes += file.size;
+                    }
+                    uploading.current = current + 1;
+                }
+                (State::Uploading(uploading), UploaderMsg::Connection(event)) => match event {
+                    ConnectionEvent::Lost => uploading.connection_lost = true,
+                    ConnectionEvent::Restored => {
+                        uploading.connection_lost = false;
+                        uploading.reconnects += 1;
                     }
+                },
+                (State::Uploading(_), UploaderMsg::Done(summary)) => {
+                    self.state = State::Finished(summary)
                 }
-                (State::Uploading(_), UploaderMsg::Done) => self.state = State::Finished,
                 (_, UploaderMsg::BadFileSystem) => {
                     self.state = State::Error("failed to read the filesystem".into());
                 }
+                (_, UploaderMsg::BadLedger(reason)) => {
+                    self.state =
+                        State::Error(format!("failed to open the upload ledger: {reason}"));
+                }
                 (_, UploaderMsg::Interrupted(reason)) => {
                     self.state = State::Error(format!("uploader was interrupted: {reason}"));
                 }
@@ -108,12 +271,169 @@ impl Uploader {
         }
     }
 
-    fn wait_for_drive(&mut self, ctx: &egui::Context) {
+    fn wait_for_drive(&mut self, ctx: &egui::Context, storage: Option<&mut (dyn Storage + '_)>) {
         egui::CentralPanel::default().show(ctx, |ui| {
             ui.vertical_centered(|ui| {
                 ui.heading("Please insert the device");
                 ui.add(Spinner::new().size(30.0));
             });
+
+            let mut changed = false;
+            ui.collapsing("Uploads", |ui| {
+                changed |= ui
+                    .add(
+                        egui::Slider::new(
+                            &mut self.settings.concurrent_uploads,
+                            1..=MAX_CONCURRENT_UPLOADS,
+                        )
+                        .text("Files uploaded at the same time"),
+                    )
+                    .changed();
+                changed |= ui
+                    .checkbox(
+                        &mut self.settings.premium,
+                        "The account has telegram premium, which allows bigger files",
+                    )
+                    .changed();
+                changed |= ui
+                    .checkbox(
+                        &mut self.settings.gps_tracks,
+                        "Reply to every video with the GPS track it recorded",
+                    )
+                    .changed();
+
+                let order = &mut self.settings.upload_order;
+                changed |= ui
+                    .checkbox(
+                        &mut order.newest_first,
+                        "Upload the most recent recordings first",
+                    )
+                    .changed();
+                ui.label("Categories, from the most to the least urgent");
+                let mut raised = None;
+                for (idx, category) in order.categories.iter().enumerate() {
+                    ui.horizontal(|ui| {
+                        if ui
+                            .add_enabled(idx > 0, egui::Button::new("Up").small())
+                            .clicked()
+                        {
+                            raised = Some(idx);
+                        }
+                        ui.label(category.tag());
+                    });
+                }
+                if let Some(idx) = raised {
+                    order.categories.swap(idx - 1, idx);
+                    changed = true;
+                }
+                if ui.button("Reset").clicked() {
+                    *order = UploadOrder::default();
+                    changed = true;
+                }
+            });
+
+            ui.collapsing("Caption", |ui| {
+                changed |= ui
+                    .add(TextEdit::multiline(&mut self.settings.caption).desired_rows(2))
+                    .changed();
+                ui.label(RichText::new(caption::PLACEHOLDERS.join(" ")).weak());
+                if ui.button("Reset").clicked() {
+                    self.settings.caption = caption::DEFAULT_TEMPLATE.into();
+                    changed = true;
+                }
+
+                let preview = caption::render(
+                    &self.settings.caption,
+                    &CaptionInfo {
+                        recorded_at: chrono::Local::now().naive_local(),
+                        camera: "VIOFO".into(),
+                        category: Category::Normal,
+                        duration: Some(Duration::from_secs(180)),
+                        resolution: Some((3840, 2160)),
+                        size: 750 * 1024 * 1024,
+                    },
+                );
+                ui.label(RichText::new(preview).monospace());
+            });
+
+            ui.collapsing("Thumbnails", |ui| {
+                ui.label(
+                    "Videos without an embedded thumbnail get one from this command, \
+                     {input} and {output} are replaced by the video and the JPEG to write",
+                );
+                changed |= ui
+                    .add(
+                        TextEdit::singleline(&mut self.settings.thumbnail_command)
+                            .hint_text(thumbnail::EXAMPLE_COMMAND)
+                            .desired_width(f32::INFINITY),
+                    )
+                    .changed();
+            });
+
+            ui.collapsing("Transcoding", |ui| {
+                ui.label(
+                    "Videos are re-encoded with this command before they're uploaded, \
+                     {input}, {output}, {bitrate} and {height} are filled in from the preset",
+                );
+                let transcoding = &mut self.settings.transcoding;
+                changed |= ui
+                    .add(
+                        TextEdit::singleline(&mut transcoding.command)
+                            .hint_text(transcode::EXAMPLE_COMMAND)
+                            .desired_width(f32::INFINITY),
+                    )
+                    .changed();
+
+                egui::ComboBox::from_label("Preset")
+                    .selected_text(transcoding.preset.label())
+                    .show_ui(ui, |ui| {
+                        for preset in Preset::ALL {
+                            changed |= ui
+                                .selectable_value(&mut transcoding.preset, preset, preset.label())
+                                .changed();
+                        }
+                    });
+
+                ui.horizontal(|ui| {
+                    ui.label("Keep the originals of");
+                    for category in [Category::Normal, Category::Event, Category::Parking] {
+                        let mut keep = transcoding.keep_original.contains(&category);
+                        if ui.checkbox(&mut keep, category.tag()).changed() {
+                            transcoding.keep_original.retain(|kept| *kept != category);
+                            if keep {
+                                transcoding.keep_original.push(category);
+                            }
+                            changed = true;
+                        }
+                    }
+                });
+            });
+
+            ui.collapsing("Trips", |ui| {
+                let trips = &mut self.settings.trips;
+                changed |= ui
+                    .checkbox(
+                        &mut trips.enabled,
+                        "Merge the consecutive clips of a drive into a single video",
+                    )
+                    .changed();
+                ui.add_enabled_ui(trips.enabled, |ui| {
+                    changed |= ui
+                        .add(
+                            egui::Slider::new(&mut trips.max_gap_secs, 0..=300)
+                                .text("Longest gap between the clips of a trip")
+                                .suffix(" s"),
+                        )
+                        .changed();
+                });
+            });
+
+            if changed {
+                self.settings_tx.send_replace(self.settings.clone());
+                if let Some(storage) = storage {
+                    self.settings.save(storage);
+                }
+            }
         });
     }
 
@@ -126,17 +446,50 @@ impl Uploader {
         });
     }
 
-    fn finished(ctx: &egui::Context) {
+    fn finished(summary: &Summary, ctx: &egui::Context) {
         egui::CentralPanel::default().show(ctx, |ui| {
             ui.vertical_centered(|ui| {
-                ui.heading(
-                    RichText::new("The backup has been completed successfully!")
-                        .color(Color32::GREEN),
-                );
-                ui.label(
-                    RichText::new("you can safely remove the device through the windows interface")
-                        .strong(),
-                );
+                if summary.failures.is_empty() {
+                    ui.heading(
+                        RichText::new("The backup has been completed successfully!")
+                            .color(Color32::GREEN),
+                    );
+                } else {
+                    ui.heading(
+                        RichText::new(format!(
+                            "The backup has been completed, but {} files have failed",
+                            summary.failures.len()
+                        ))
+                        .color(Color32::YELLOW),
+                    );
+                }
+                ui.label(format!(
+                    "uploaded: {}, duplicates: {}",
+                    summary.uploaded, summary.duplicates
+                ));
+                if summary.repaired + summary.unrecoverable > 0 {
+                    ui.label(
+                        RichText::new(format!(
+                            "broken clips: {} repaired, {} uploaded as they are",
+                            summary.repaired, summary.unrecoverable
+                        ))
+                        .color(Color32::YELLOW),
+                    );
+                }
+
+                egui::ScrollArea::vertical()
+                    .max_height(100.0)
+                    .show(ui, |ui| {
+                        for failure in &summary.failures {
+                            ui.label(
+                                RichText::new(format!("{}: {}", failure.name, failure.reason))
+                                    .monospace()
+                                    .color(Color32::RED),
+                            );
+                        }
+                    });
+
+                ui.label(RichText::new("you can now safely remove the device").strong());
             });
         });
     }
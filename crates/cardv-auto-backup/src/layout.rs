@@ -0,0 +1,239 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use glob::{MatchOptions, Pattern, PatternError};
+
+/// What kind of recordings a folder holds
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Category {
+    Normal,
+    /// Locked clips recorded after an incident (e.g. `EMR`, `RO`)
+    Event,
+    Parking,
+    Photo,
+}
+
+impl Category {
+    /// The (lowercase) file extensions that belong to the category
+    pub fn extensions(&self) -> &'static [&'static str] {
+        match self {
+            Self::Normal | Self::Event | Self::Parking => &["mp4"],
+            Self::Photo => &["jpg", "jpeg"],
+        }
+    }
+
+    /// A hashtag that makes the category searchable in the channel
+    pub fn tag(&self) -> &'static str {
+        match self {
+            Self::Normal => "#normal",
+            Self::Event => "#event",
+            Self::Parking => "#parking",
+            Self::Photo => "#photo",
+        }
+    }
+}
+
+/// Describes where a dashcam model stores its recordings on the card
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct LayoutProfile {
+    pub name: String,
+    pub folders: Vec<FolderRule>,
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct FolderRule {
+    /// Glob pattern of the folders, relative to the root of the card
+    ///
+    /// every path component is matched on its own (e.g. `*/Movie`),
+    /// so recursive wildcards (`**`) are not supported.
+    pub pattern: String,
+    pub category: Category,
+}
+
+/// A folder on the card that should be backed up
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SourceFolder {
+    pub path: PathBuf,
+    pub category: Category,
+}
+
+impl LayoutProfile {
+    fn new(name: &str, folders: &[(&str, Category)]) -> Self {
+        Self {
+            name: name.into(),
+            folders: folders
+                .iter()
+                .map(|(pattern, category)| FolderRule {
+                    pattern: pattern.to_string(),
+                    category: *category,
+                })
+                .collect(),
+        }
+    }
+
+    /// Lists the folders on the card that match this profile
+    ///
+    /// a folder that is matched by several rules gets the category of the first one
+    pub fn find_folders(&self, root: &Path) -> Vec<SourceFolder> {
+        let mut folders: Vec<SourceFolder> = vec![];
+        for rule in &self.folders {
+            let paths = match match_folders(root, &rule.pattern) {
+                Ok(paths) => paths,
+                Err(err) => {
+                    tracing::warn!(
+                        profile = self.name,
+                        "bad folder pattern '{}': {err}",
+                        rule.pattern
+                    );
+                    continue;
+                }
+            };
+
+            for path in paths {
+                if !folders.iter().any(|folder| folder.path == path) {
+                    folders.push(SourceFolder {
+                        path,
+                        category: rule.category,
+                    });
+                }
+            }
+        }
+        folders.sort_by(|a, b| a.path.cmp(&b.path));
+
+        folders
+    }
+}
+
+/// Walks down from the root, matching every path component against its glob
+///
+/// the components are matched case-insensitively because
+/// dashcams are not consistent about the casing.
+fn match_folders(root: &Path, pattern: &str) -> Result<Vec<PathBuf>, PatternError> {
+    let options = MatchOptions {
+        case_sensitive: false,
+        ..Default::default()
+    };
+
+    let mut folders = vec![root.to_path_buf()];
+    for component in pattern.split(['/', '\\']).filter(|part| !part.is_empty()) {
+        let component = Pattern::new(component)?;
+        folders = folders
+            .iter()
+            .filter_map(|folder| fs::read_dir(folder).ok())
+            .flatten()
+            .filter_map(Result::ok)
+            .filter(|entry| {
+                component.matches_with(&entry.file_name().to_string_lossy(), options)
+                    && entry.path().is_dir()
+            })
+            .map(|entry| entry.path())
+            .collect();
+    }
+
+    Ok(folders)
+}
+
+/// The layouts of common dashcam brands
+pub fn builtin_profiles() -> Vec<LayoutProfile> {
+    use Category::*;
+
+    vec![
+        LayoutProfile::new(
+            "Novatek",
+            &[
+                ("CARDV/Movie", Normal),
+                ("CARDV/EMR", Event),
+                ("CARDV/Photo", Photo),
+            ],
+        ),
+        LayoutProfile::new(
+            "Viofo",
+            &[
+                ("DCIM/Movie", Normal),
+                ("DCIM/Movie/RO", Event),
+                ("DCIM/Movie/Parking", Parking),
+                ("DCIM/Photo", Photo),
+            ],
+        ),
+        LayoutProfile::new(
+            "70mai",
+            // dual-channel models split each folder into 'Front' and 'Back'
+            &[
+                ("Normal", Normal),
+                ("Normal/*", Normal),
+                ("Event", Event),
+                ("Event/*", Event),
+                ("Parking", Parking),
+                ("Parking/*", Parking),
+                ("Photo", Photo),
+            ],
+        ),
+        LayoutProfile::new("BlackVue", &[("BlackVue/Record", Normal)]),
+    ]
+}
+
+/// Finds the first profile that matches the card
+///
+/// returns the matching profile along with all the folders to back up
+pub fn detect_layout<'a>(
+    profiles: &'a [LayoutProfile],
+    root: &Path,
+) -> Option<(&'a LayoutProfile, Vec<SourceFolder>)> {
+    profiles.iter().find_map(|profile| {
+        let folders = profile.find_folders(root);
+        (!folders.is_empty()).then_some((profile, folders))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::{builtin_profiles, detect_layout, Category, LayoutProfile, SourceFolder};
+
+    #[test]
+    fn detect_builtin_and_custom_layouts() {
+        let card = tempfile::tempdir().unwrap();
+        fs::create_dir_all(card.path().join("Normal").join("Front")).unwrap();
+        fs::create_dir_all(card.path().join("event")).unwrap();
+
+        let profiles = builtin_profiles();
+        let (profile, folders) = detect_layout(&profiles, card.path()).unwrap();
+        assert_eq!(profile.name, "70mai");
+        assert_eq!(
+            folders,
+            vec![
+                SourceFolder {
+                    path: card.path().join("Normal"),
+                    category: Category::Normal,
+                },
+                SourceFolder {
+                    path: card.path().join("Normal").join("Front"),
+                    category: Category::Normal,
+                },
+                SourceFolder {
+                    path: card.path().join("event"),
+                    category: Category::Event,
+                },
+            ]
+        );
+
+        let custom = vec![LayoutProfile::new(
+            "custom",
+            &[("*/Front", Category::Parking)],
+        )];
+        let (_, folders) = detect_layout(&custom, card.path()).unwrap();
+        assert_eq!(
+            folders,
+            vec![SourceFolder {
+                path: card.path().join("Normal").join("Front"),
+                category: Category::Parking,
+            }]
+        );
+
+        assert!(detect_layout(&custom, &card.path().join("Normal")).is_none());
+    }
+}
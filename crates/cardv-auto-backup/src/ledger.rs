@@ -0,0 +1,305 @@
+use std::{
+    collections::HashMap,
+    fs::{self, File, OpenOptions},
+    io::{self, Read, Write},
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use chrono::NaiveDateTime;
+
+use crate::clip::Channel;
+
+type Key = (Option<String>, String, u64, u64);
+type ClipKey = (Option<String>, NaiveDateTime, Option<Channel>, u64);
+
+/// Identifies a recording independently of where the card is mounted
+#[derive(Debug, Clone, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
+pub struct FileIdentity {
+    /// The serial of the card the file was found on
+    pub card_serial: Option<String>,
+    /// The path relative to the root of the card, with '/' as a separator
+    pub relative_path: String,
+    pub size: u64,
+    /// The modification time, in seconds since the unix epoch
+    pub mtime: u64,
+    /// The content hash, when it was computed
+    pub hash: Option<String>,
+    /// When the clip was recorded, if its name or metadata tells
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub recorded_at: Option<NaiveDateTime>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub channel: Option<Channel>,
+}
+
+impl FileIdentity {
+    pub fn new(
+        card_serial: Option<String>,
+        root: &Path,
+        path: &Path,
+        metadata: &fs::Metadata,
+    ) -> io::Result<Self> {
+        let relative_path = path
+            .strip_prefix(root)
+            .unwrap_or(path)
+            .components()
+            .map(|component| component.as_os_str().to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("/");
+
+        Ok(Self {
+            card_serial,
+            relative_path,
+            size: metadata.len(),
+            mtime: unix_time(metadata.modified()?),
+            hash: None,
+            recorded_at: None,
+            channel: None,
+        })
+    }
+
+    /// Everything but the hash, which may not be known yet
+    fn key(&self) -> Key {
+        (
+            self.card_serial.clone(),
+            self.relative_path.clone(),
+            self.size,
+            self.mtime,
+        )
+    }
+
+    /// Identifies the clip even after the camera renamed or moved it (e.g. when it was locked)
+    fn clip_key(&self) -> Option<ClipKey> {
+        Some((
+            self.card_serial.clone(),
+            self.recorded_at?,
+            self.channel,
+            self.size,
+        ))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum UploadStatus {
+    Uploaded,
+    /// The same content was already uploaded from another file,
+    /// the message id refers to the original upload
+    Duplicate,
+    /// Something was wrong with the file itself
+    Skipped,
+    /// The upload kept failing until we ran out of attempts
+    Failed,
+}
+
+/// A single line in the journal
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct LedgerEntry {
+    pub file: FileIdentity,
+    pub status: UploadStatus,
+    /// The id of the telegram message that holds the file
+    pub message_id: Option<i32>,
+    /// When the entry was recorded, in seconds since the unix epoch
+    pub timestamp: u64,
+    /// Why the file couldn't be uploaded
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// The content hash of the re-encoded copy that was uploaded instead of the file
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub transcoded_hash: Option<String>,
+}
+
+impl LedgerEntry {
+    pub fn new(file: FileIdentity, status: UploadStatus, message_id: Option<i32>) -> Self {
+        Self {
+            file,
+            status,
+            message_id,
+            timestamp: unix_time(SystemTime::now()),
+            error: None,
+            transcoded_hash: None,
+        }
+    }
+}
+
+/// The history of all the uploads, kept as an append-only JSON-lines journal
+///
+/// the last entry of a file wins, so a status is
+/// updated by simply appending a new entry.
+#[derive(Debug)]
+pub struct Ledger {
+    journal: File,
+    entries: HashMap<Key, LedgerEntry>,
+    /// The uploaded entries by their content hash
+    hashes: HashMap<String, Key>,
+    /// The uploaded entries by their recording time
+    clips: HashMap<ClipKey, Key>,
+}
+
+impl Ledger {
+    /// Opens the journal, creating it if it doesn't exist yet
+    pub fn open(path: &Path) -> io::Result<Self> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let mut journal = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .create(true)
+            .open(path)?;
+        let mut content = String::new();
+        journal.read_to_string(&mut content)?;
+
+        let mut ledger = Self {
+            journal,
+            entries: Default::default(),
+            hashes: Default::default(),
+            clips: Default::default(),
+        };
+        for line in content.lines().filter(|line| !line.trim().is_empty()) {
+            match serde_json::from_str::<LedgerEntry>(line) {
+                Ok(entry) => ledger.insert(entry),
+                Err(err) => tracing::warn!(?err, "skipping a corrupted ledger entry"),
+            }
+        }
+
+        // the last line may be cut short if we were killed mid-write,
+        // make sure the next entry won't be glued to it
+        if !content.is_empty() && !content.ends_with('\n') {
+            ledger.journal.write_all(b"\n")?;
+        }
+
+        Ok(ledger)
+    }
+
+    fn insert(&mut self, entry: LedgerEntry) {
+        let key = entry.file.key();
+        if let (Some(hash), UploadStatus::Uploaded) = (&entry.file.hash, entry.status) {
+            self.hashes.insert(hash.clone(), key.clone());
+        }
+        if let (Some(clip), UploadStatus::Uploaded) = (entry.file.clip_key(), entry.status) {
+            self.clips.insert(clip, key.clone());
+        }
+        self.entries.insert(key, entry);
+    }
+
+    /// Appends an entry to the journal
+    pub fn record(&mut self, entry: LedgerEntry) -> io::Result<()> {
+        let mut line =
+            serde_json::to_string(&entry).expect("serializing into string should never fail");
+        line.push('\n');
+        self.journal.write_all(line.as_bytes())?;
+        self.journal.sync_data()?;
+
+        self.insert(entry);
+
+        Ok(())
+    }
+
+    pub fn get(&self, file: &FileIdentity) -> Option<&LedgerEntry> {
+        self.entries.get(&file.key())
+    }
+
+    /// Whether the file was already taken care of, either uploaded or found to be a duplicate
+    pub fn is_uploaded(&self, file: &FileIdentity) -> bool {
+        self.get(file).is_some_and(|entry| {
+            matches!(
+                entry.status,
+                UploadStatus::Uploaded | UploadStatus::Duplicate
+            )
+        })
+    }
+
+    /// Finds a previous upload with the same content
+    pub fn find_by_hash(&self, hash: &str) -> Option<&LedgerEntry> {
+        self.hashes.get(hash).and_then(|key| self.entries.get(key))
+    }
+
+    /// Finds a previous upload of the same clip from the same card, which is cheaper than hashing
+    pub fn find_by_clip(&self, file: &FileIdentity) -> Option<&LedgerEntry> {
+        self.clips
+            .get(&file.clip_key()?)
+            .and_then(|key| self.entries.get(key))
+    }
+}
+
+/// Computes the BLAKE3 hash of the file, streaming it from disk
+pub async fn content_hash(path: PathBuf) -> io::Result<String> {
+    tokio::task::spawn_blocking(move || {
+        let mut hasher = blake3::Hasher::new();
+        io::copy(&mut File::open(path)?, &mut hasher)?;
+
+        Ok(hasher.finalize().to_hex().to_string())
+    })
+    .await?
+}
+
+fn unix_time(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|since| since.as_secs())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs::OpenOptions, io::Write};
+
+    use super::{FileIdentity, Ledger, LedgerEntry, UploadStatus};
+
+    fn identity(relative_path: &str, size: u64) -> FileIdentity {
+        FileIdentity {
+            card_serial: Some("1234-ABCD".into()),
+            relative_path: relative_path.into(),
+            size,
+            mtime: 1_700_000_000,
+            hash: None,
+            recorded_at: None,
+            channel: None,
+        }
+    }
+
+    #[test]
+    fn replay_journal() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("ledger.jsonl");
+
+        let mut ledger = Ledger::open(&path).unwrap();
+        ledger
+            .record(LedgerEntry::new(
+                identity("CARDV/Movie/a.mp4", 10),
+                UploadStatus::Uploaded,
+                Some(7),
+            ))
+            .unwrap();
+        drop(ledger);
+
+        // simulate a write that was cut short
+        let mut journal = OpenOptions::new().append(true).open(&path).unwrap();
+        journal.write_all(br#"{"file":{"card"#).unwrap();
+
+        let mut ledger = Ledger::open(&path).unwrap();
+        ledger
+            .record(LedgerEntry::new(
+                FileIdentity {
+                    hash: Some("b-hash".into()),
+                    ..identity("CARDV/Movie/b.mp4", 10)
+                },
+                UploadStatus::Uploaded,
+                Some(8),
+            ))
+            .unwrap();
+        drop(ledger);
+
+        let ledger = Ledger::open(&path).unwrap();
+        let entry = ledger.get(&identity("CARDV/Movie/a.mp4", 10)).unwrap();
+        assert_eq!(entry.message_id, Some(7));
+        assert!(ledger.is_uploaded(&identity("CARDV/Movie/a.mp4", 10)));
+        assert!(ledger.is_uploaded(&identity("CARDV/Movie/b.mp4", 10)));
+        assert_eq!(
+            ledger.find_by_hash("b-hash").map(|entry| entry.message_id),
+            Some(Some(8))
+        );
+        // the camera re-used the name after a clock reset
+        assert!(!ledger.is_uploaded(&identity("CARDV/Movie/a.mp4", 11)));
+    }
+}
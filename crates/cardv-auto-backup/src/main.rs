@@ -4,13 +4,31 @@ use std::time::Duration;
 
 use eframe::egui;
 
+mod caption;
+mod clip;
+mod command;
+mod destination;
 mod execution_state;
+mod gps;
 mod gui;
+mod layout;
+mod ledger;
+mod mp4_file;
+mod queue;
+mod repair;
+mod settings;
+mod split;
+mod temp;
 mod tg;
+mod thumbnail;
+mod transcode;
+mod trip;
 mod usb;
+mod video;
 
 use gui::App;
 
+const APP_ID: &str = "CARDV_AUTO_BACKUP";
 const PACKED_BOT_STORAGE_KEY: &str = "PACKED_BOT";
 
 fn main() -> Result<(), eframe::Error> {
@@ -25,7 +43,7 @@ fn main() -> Result<(), eframe::Error> {
     let options = eframe::NativeOptions {
         viewport: egui::ViewportBuilder::default()
             .with_inner_size([650.0, 350.0])
-            .with_app_id("CARDV_AUTO_BACKUP"),
+            .with_app_id(APP_ID),
         centered: true,
         follow_system_theme: true,
         ..Default::default()
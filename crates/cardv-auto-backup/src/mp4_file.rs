@@ -0,0 +1,132 @@
+//! Reading and writing of the mp4s that are merged, split or repaired
+
+use std::{
+    fs::File,
+    io::{self, BufReader, BufWriter, Read, Seek, Write},
+    path::Path,
+};
+
+use mp4::{
+    AacConfig, AvcConfig, HevcConfig, MediaConfig, MediaType, Mp4Config, Mp4Reader, Mp4Sample,
+    Mp4Track, Mp4Writer, TrackConfig, TtxtConfig, Vp9Config,
+};
+
+#[derive(thiserror::Error, Debug)]
+pub enum Mp4Err {
+    #[error("{0}")]
+    Io(#[from] io::Error),
+
+    #[error("failed to process the mp4 ({0})")]
+    Mp4(#[from] mp4::Error),
+
+    #[error("h265 video can't be copied without losing its parameter sets")]
+    Hevc,
+}
+
+/// Converts io and mp4 errors into the `File` variant of an error, so `?` works on both
+macro_rules! impl_from_mp4_err {
+    ($err:ty) => {
+        impl From<std::io::Error> for $err {
+            fn from(err: std::io::Error) -> Self {
+                Self::File(err.into())
+            }
+        }
+
+        impl From<mp4::Error> for $err {
+            fn from(err: mp4::Error) -> Self {
+                Self::File(err.into())
+            }
+        }
+    };
+}
+pub(crate) use impl_from_mp4_err;
+
+pub fn open(path: &Path) -> Result<Mp4Reader<BufReader<File>>, Mp4Err> {
+    let file = File::open(path)?;
+    let size = file.metadata()?.len();
+    Ok(Mp4Reader::read_header(BufReader::new(file), size)?)
+}
+
+/// A new mp4, with the brands and timescale of the one its samples come from
+pub struct Mp4Output {
+    writer: Mp4Writer<BufWriter<File>>,
+}
+
+impl Mp4Output {
+    pub fn create<'a, R: Read + Seek>(
+        path: &Path,
+        like: &Mp4Reader<R>,
+        tracks: impl IntoIterator<Item = &'a TrackConfig>,
+    ) -> Result<Self, Mp4Err> {
+        let config = Mp4Config {
+            major_brand: *like.major_brand(),
+            minor_version: like.minor_version(),
+            compatible_brands: like.compatible_brands().to_vec(),
+            timescale: like.timescale(),
+        };
+        let mut writer = Mp4Writer::write_start(BufWriter::new(File::create(path)?), &config)?;
+        for track in tracks {
+            writer.add_track(track)?;
+        }
+
+        Ok(Self { writer })
+    }
+
+    /// Writes a sample of the `idx`th track, in the order they were given to [`Self::create`]
+    pub fn write_sample(&mut self, idx: usize, sample: &Mp4Sample) -> Result<(), Mp4Err> {
+        // the writer numbers the tracks in the order they were added, from 1
+        Ok(self.writer.write_sample(idx as u32 + 1, sample)?)
+    }
+
+    /// Writes the moov box, which the mp4 can't be played without
+    pub fn finish(mut self) -> Result<(), Mp4Err> {
+        self.writer.write_end()?;
+        self.writer.into_writer().flush()?;
+        Ok(())
+    }
+}
+
+/// Fails for h265 video, whose samples can't be copied into a new mp4 as they are
+///
+/// the hvcC box the mp4 crate writes has no VPS/SPS/PPS, so the copy could
+/// only be decoded if the camera happens to repeat them in the stream.
+pub fn ensure_copyable(track: &Mp4Track) -> Result<(), Mp4Err> {
+    match track.media_type() {
+        Ok(MediaType::H265) => Err(Mp4Err::Hevc),
+        _ => Ok(()),
+    }
+}
+
+/// How to write a track like the given one, fails for the codecs the mp4 crate can't write
+pub fn track_config(track: &Mp4Track) -> mp4::Result<TrackConfig> {
+    let media_conf = match track.media_type()? {
+        MediaType::H264 => MediaConfig::AvcConfig(AvcConfig {
+            width: track.width(),
+            height: track.height(),
+            seq_param_set: track.sequence_parameter_set()?.to_vec(),
+            pic_param_set: track.picture_parameter_set()?.to_vec(),
+        }),
+        MediaType::H265 => MediaConfig::HevcConfig(HevcConfig {
+            width: track.width(),
+            height: track.height(),
+        }),
+        MediaType::VP9 => MediaConfig::Vp9Config(Vp9Config {
+            width: track.width(),
+            height: track.height(),
+        }),
+        MediaType::AAC => MediaConfig::AacConfig(AacConfig {
+            bitrate: track.bitrate(),
+            profile: track.audio_profile()?,
+            freq_index: track.sample_freq_index()?,
+            chan_conf: track.channel_config()?,
+        }),
+        MediaType::TTXT => MediaConfig::TtxtConfig(TtxtConfig {}),
+    };
+
+    Ok(TrackConfig {
+        track_type: track.track_type()?,
+        timescale: track.timescale(),
+        language: track.language().to_string(),
+        media_conf,
+    })
+}
@@ -0,0 +1,162 @@
+use std::{cmp::Ordering, collections::BinaryHeap};
+
+use crate::{layout::Category, usb::Recording};
+
+/// The order in which the recordings are uploaded
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+pub struct UploadOrder {
+    /// Categories from the most to the least urgent
+    ///
+    /// categories that are not listed go last, leave
+    /// it empty to upload purely by recording time.
+    pub categories: Vec<Category>,
+    /// Within a category, upload the most recent recordings first
+    pub newest_first: bool,
+}
+
+impl Default for UploadOrder {
+    fn default() -> Self {
+        Self {
+            categories: vec![
+                Category::Event,
+                Category::Parking,
+                Category::Normal,
+                Category::Photo,
+            ],
+            newest_first: false,
+        }
+    }
+}
+
+/// A priority queue of the recordings that are waiting to be uploaded
+#[derive(Debug)]
+pub struct UploadQueue {
+    order: UploadOrder,
+    heap: BinaryHeap<Queued>,
+}
+
+#[derive(Debug)]
+struct Queued {
+    // the heap pops the greatest key first
+    key: (usize, i128),
+    recording: Recording,
+}
+
+impl UploadQueue {
+    pub fn new(order: UploadOrder) -> Self {
+        Self {
+            order,
+            heap: Default::default(),
+        }
+    }
+
+    pub fn push(&mut self, recording: Recording) {
+        let rank = self
+            .order
+            .categories
+            .iter()
+            .position(|category| *category == recording.category)
+            .unwrap_or(self.order.categories.len());
+        let time = recording.clip.recorded_at.and_utc().timestamp() as i128;
+        let time = if self.order.newest_first { time } else { -time };
+
+        self.heap.push(Queued {
+            key: (usize::MAX - rank, time),
+            recording,
+        });
+    }
+
+    /// Takes the most urgent recording
+    pub fn pop(&mut self) -> Option<Recording> {
+        self.heap.pop().map(|queued| queued.recording)
+    }
+
+    /// The recording that will be popped next
+    pub fn peek(&self) -> Option<&Recording> {
+        self.heap.peek().map(|queued| &queued.recording)
+    }
+
+    pub fn len(&self) -> usize {
+        self.heap.len()
+    }
+
+    /// The combined size of all the queued recordings, in bytes
+    pub fn size(&self) -> u64 {
+        self.heap
+            .iter()
+            .map(|queued| queued.recording.total_size())
+            .sum()
+    }
+}
+
+impl Extend<Recording> for UploadQueue {
+    fn extend<T: IntoIterator<Item = Recording>>(&mut self, iter: T) {
+        for recording in iter {
+            self.push(recording);
+        }
+    }
+}
+
+impl PartialEq for Queued {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Queued {}
+
+impl PartialOrd for Queued {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Queued {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.key
+            .cmp(&other.key)
+            // fall back to the alphabetical order (reversed, since the heap pops the greatest)
+            .then_with(|| other.recording.path.cmp(&self.recording.path))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{UploadOrder, UploadQueue};
+    use crate::{layout::Category, usb::fake::recording};
+
+    fn drain(mut queue: UploadQueue) -> Vec<String> {
+        std::iter::from_fn(|| queue.pop())
+            .map(|recording| recording.path.to_string_lossy().into_owned())
+            .collect()
+    }
+
+    #[test]
+    fn events_before_normal_footage() {
+        let recordings = [
+            recording("normal-1", Category::Normal, 60),
+            recording("event-3", Category::Event, 180),
+            recording("parking-2", Category::Parking, 120),
+            recording("normal-0", Category::Normal, 0),
+            recording("event-2", Category::Event, 120),
+        ];
+
+        let mut queue = UploadQueue::new(UploadOrder::default());
+        queue.extend(recordings.clone());
+        assert_eq!(
+            drain(queue),
+            ["event-2", "event-3", "parking-2", "normal-0", "normal-1"]
+        );
+
+        let mut queue = UploadQueue::new(UploadOrder {
+            categories: vec![],
+            newest_first: true,
+        });
+        queue.extend(recordings);
+        assert_eq!(
+            drain(queue),
+            ["event-3", "event-2", "parking-2", "normal-1", "normal-0"]
+        );
+    }
+}
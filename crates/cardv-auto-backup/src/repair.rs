@@ -0,0 +1,415 @@
+//! Recovery of clips the camera didn't finish writing
+//!
+//! dashcams write the moov box (the index of the samples) when a clip ends,
+//! so the clip that was recording when the power was cut is left with only
+//! its mdat. the video samples in it can still be found, as h264/h265
+//! samples are made of length-prefixed NAL units with recognizable headers,
+//! and a healthy clip of the same camera tells how to index them.
+
+use std::{
+    fs::{self, File},
+    io::{self, BufReader, Read, Seek, SeekFrom},
+    path::{Path, PathBuf},
+};
+
+use mp4::{MediaType, Mp4Sample, TrackType};
+
+use crate::{
+    clip::ClipInfo,
+    mp4_file::{self, impl_from_mp4_err, Mp4Err, Mp4Output},
+};
+
+/// Larger NAL units are taken for garbage, even 4K keyframes are far smaller
+const MAX_NAL_SIZE: u32 = 16 * 1024 * 1024;
+/// How much is read at a time while looking for the next video sample past
+/// audio or metadata chunks, which are usually far smaller
+const RESYNC_WINDOW: usize = 64 * 1024;
+
+#[derive(thiserror::Error, Debug)]
+pub enum RepairErr {
+    #[error(transparent)]
+    File(#[from] Mp4Err),
+
+    #[error("there is no healthy clip of the same camera to learn the format from")]
+    NoReference,
+
+    #[error("the reference clip's {0} video can't be recovered")]
+    UnsupportedCodec(String),
+
+    #[error("no media data was found")]
+    NoMediaData,
+
+    #[error("no video frames were found")]
+    NoFrames,
+}
+
+impl_from_mp4_err!(RepairErr);
+
+/// Whether the clip can't be read as an mp4, e.g. as its moov box is missing
+pub fn is_broken(path: &Path) -> io::Result<bool> {
+    match mp4_file::open(path) {
+        Err(Mp4Err::Io(err)) => Err(err),
+        res => Ok(res.is_err()),
+    }
+}
+
+/// Finds a healthy clip recorded by the same lens, next to the broken one
+pub fn find_reference(path: &Path) -> Option<PathBuf> {
+    let name = |path: &Path| {
+        path.file_name()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .into_owned()
+    };
+    let channel = ClipInfo::from_name(&name(path)).and_then(|clip| clip.channel);
+
+    let mut siblings = fs::read_dir(path.parent()?)
+        .ok()?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|sibling| sibling != path && sibling.extension() == path.extension())
+        .filter(|sibling| {
+            ClipInfo::from_name(&name(sibling)).and_then(|clip| clip.channel) == channel
+        })
+        .collect::<Vec<_>>();
+    // the latest clips are the likeliest to share the broken one's settings
+    siblings.sort_by_key(|sibling| std::cmp::Reverse(name(sibling)));
+
+    siblings
+        .into_iter()
+        .find(|sibling| is_broken(sibling).is_ok_and(|broken| !broken))
+}
+
+/// Rebuilds the video of a broken clip into `out`, returning how many frames were recovered
+///
+/// only the video track is recovered, the audio samples can't be told apart
+/// reliably. the frames are assumed to be in presentation order with a constant
+/// frame rate, which holds for dashcams as they don't use b-frames.
+pub fn repair(path: &Path, reference: &Path, out: &Path) -> Result<u32, RepairErr> {
+    let reference = mp4_file::open(reference)?;
+
+    let track = reference
+        .tracks()
+        .values()
+        .find(|track| track.track_type().ok() == Some(TrackType::Video))
+        .ok_or(RepairErr::NoReference)?;
+    let codec = match track.media_type()? {
+        MediaType::H264 => Codec::H264,
+        MediaType::H265 => Codec::H265,
+        other => return Err(RepairErr::UnsupportedCodec(other.to_string())),
+    };
+    let track_config = mp4_file::track_config(track)?;
+    let frame_duration = track
+        .trak
+        .mdia
+        .minf
+        .stbl
+        .stts
+        .entries
+        .first()
+        .map(|entry| entry.sample_delta)
+        .ok_or(RepairErr::NoReference)?;
+
+    let mut file = BufReader::new(File::open(path)?);
+    let (start, end) = find_mdat(&mut file)?.ok_or(RepairErr::NoMediaData)?;
+
+    let mut writer = Mp4Output::create(out, &reference, [&track_config])?;
+
+    let mut frames = 0;
+    let mut seen_keyframe = false;
+    let res = scan_frames(&mut file, start, end, codec, |frame| {
+        // the decoder can't start before the first keyframe
+        seen_keyframe |= frame.is_sync;
+        if !seen_keyframe {
+            return Ok(());
+        }
+
+        writer.write_sample(
+            0,
+            &Mp4Sample {
+                start_time: u64::from(frames) * u64::from(frame_duration),
+                duration: frame_duration,
+                rendering_offset: 0,
+                is_sync: frame.is_sync,
+                bytes: frame.bytes.into(),
+            },
+        )?;
+        frames += 1;
+        Ok(())
+    });
+
+    let res = res.and_then(|_| {
+        if frames == 0 {
+            return Err(RepairErr::NoFrames);
+        }
+        writer.finish()?;
+        Ok(frames)
+    });
+    if res.is_err() {
+        let _ = fs::remove_file(out);
+    }
+
+    res
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Codec {
+    H264,
+    H265,
+}
+
+impl Codec {
+    fn header_size(self) -> usize {
+        match self {
+            Self::H264 => 1,
+            Self::H265 => 2,
+        }
+    }
+
+    /// Reads the NAL unit's header, `None` if it can't be one
+    fn parse(self, header: &[u8]) -> Option<Nal> {
+        match self {
+            Self::H264 => {
+                let kind = header[0] & 0x1F;
+                if header[0] & 0x80 != 0 || !(1..=12).contains(&kind) {
+                    return None;
+                }
+                Some(Nal {
+                    is_slice: matches!(kind, 1 | 5),
+                    is_sync: kind == 5,
+                })
+            }
+            Self::H265 => {
+                let kind = (header[0] >> 1) & 0x3F;
+                let layer = ((header[0] & 0x01) << 5) | (header[1] >> 3);
+                let temporal_id = header[1] & 0x07;
+                if header[0] & 0x80 != 0
+                    || !(kind <= 21 || (32..=40).contains(&kind))
+                    || layer != 0
+                    || temporal_id == 0
+                {
+                    return None;
+                }
+                Some(Nal {
+                    is_slice: kind <= 21,
+                    is_sync: (16..=21).contains(&kind),
+                })
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Nal {
+    is_slice: bool,
+    is_sync: bool,
+}
+
+#[derive(Debug, Default)]
+struct Frame {
+    bytes: Vec<u8>,
+    is_sync: bool,
+    has_slice: bool,
+}
+
+/// Where the media data starts and ends, as far as the file goes
+fn find_mdat(file: &mut (impl Read + Seek)) -> io::Result<Option<(u64, u64)>> {
+    let len = file.seek(SeekFrom::End(0))?;
+    let mut offset = 0;
+    while offset + 8 <= len {
+        file.seek(SeekFrom::Start(offset))?;
+        let mut header = [0; 8];
+        file.read_exact(&mut header)?;
+
+        let (header_size, size) = match u32::from_be_bytes(header[..4].try_into().unwrap()) {
+            0 => (8, len - offset),
+            1 => {
+                let mut size = [0; 8];
+                file.read_exact(&mut size)?;
+                (16, u64::from_be_bytes(size))
+            }
+            size => (8, size.into()),
+        };
+        if &header[4..] == b"mdat" {
+            // the declared size is often bogus when the clip wasn't finished
+            let end = offset.saturating_add(size).min(len);
+            return Ok(Some((offset + header_size, end)));
+        }
+        if size < header_size {
+            return Ok(None);
+        }
+        offset += size;
+    }
+
+    Ok(None)
+}
+
+/// Walks the media data, passing every video frame found to `on_frame`
+///
+/// the chunks of other tracks are skipped by looking for the next place
+/// that reads as two NAL units in a row.
+fn scan_frames(
+    file: &mut (impl Read + Seek),
+    start: u64,
+    end: u64,
+    codec: Codec,
+    mut on_frame: impl FnMut(Frame) -> Result<(), RepairErr>,
+) -> Result<(), RepairErr> {
+    let mut frame = Frame::default();
+    let mut window = vec![0; RESYNC_WINDOW];
+    let mut offset = start;
+    while offset < end {
+        match read_nal(file, offset, end, codec)? {
+            Some((nal, bytes)) => {
+                // a new frame starts with a non-slice unit (e.g. parameter sets)
+                // or with the first slice of the next picture
+                let first_slice = nal.is_slice
+                    && bytes
+                        .get(4 + codec.header_size())
+                        .is_some_and(|byte| byte & 0x80 != 0);
+                if frame.has_slice && (!nal.is_slice || first_slice) {
+                    on_frame(std::mem::take(&mut frame))?;
+                }
+
+                offset += bytes.len() as u64;
+                frame.is_sync |= nal.is_sync;
+                frame.has_slice |= nal.is_slice;
+                frame.bytes.extend(bytes);
+            }
+            None => {
+                if frame.has_slice {
+                    on_frame(std::mem::take(&mut frame))?;
+                }
+                frame = Frame::default();
+
+                match resync(file, &mut window, offset + 1, end, codec)? {
+                    Some(next) => offset = next,
+                    None => break,
+                }
+            }
+        }
+    }
+
+    // the last frame is likely cut short
+    Ok(())
+}
+
+/// Reads the length-prefixed NAL unit at `offset`, `None` if there is none
+fn read_nal(
+    file: &mut (impl Read + Seek),
+    offset: u64,
+    end: u64,
+    codec: Codec,
+) -> io::Result<Option<(Nal, Vec<u8>)>> {
+    let mut prefix = [0; 6];
+    file.seek(SeekFrom::Start(offset))?;
+    if offset + 6 > end || file.read_exact(&mut prefix).is_err() {
+        return Ok(None);
+    }
+
+    let Some((nal, len)) = nal_at(&prefix, codec) else {
+        return Ok(None);
+    };
+    if offset + 4 + u64::from(len) > end {
+        return Ok(None);
+    }
+
+    let mut bytes = vec![0; 4 + len as usize];
+    bytes[..6].copy_from_slice(&prefix);
+    file.read_exact(&mut bytes[6..])?;
+    Ok(Some((nal, bytes)))
+}
+
+fn nal_at(prefix: &[u8], codec: Codec) -> Option<(Nal, u32)> {
+    let len = u32::from_be_bytes(prefix.get(..4)?.try_into().unwrap());
+    if len < codec.header_size() as u32 + 1 || len > MAX_NAL_SIZE {
+        return None;
+    }
+    Some((codec.parse(prefix.get(4..6)?)?, len))
+}
+
+/// Finds the next offset that reads as two consecutive NAL units
+///
+/// the data is read a `window` at a time, so a chunk to skip is only read once.
+fn resync(
+    file: &mut (impl Read + Seek),
+    window: &mut [u8],
+    from: u64,
+    end: u64,
+    codec: Codec,
+) -> io::Result<Option<u64>> {
+    let mut offset = from;
+    while offset < end {
+        file.seek(SeekFrom::Start(offset))?;
+        let len = (end - offset).min(window.len() as u64) as usize;
+        file.read_exact(&mut window[..len])?;
+
+        for at in 0..len {
+            let Some((_, nal_len)) = nal_at(&window[at..len], codec) else {
+                continue;
+            };
+            let next = at + 4 + nal_len as usize;
+            let confirmed = match window.get(next..(next + 6).min(len)) {
+                Some(prefix) if prefix.len() == 6 => nal_at(prefix, codec).is_some(),
+                // the end of the data
+                _ if offset + next as u64 + 6 > end => true,
+                // the next unit is past the window
+                _ => {
+                    let mut prefix = [0; 6];
+                    file.seek(SeekFrom::Start(offset + next as u64))?;
+                    file.read_exact(&mut prefix)?;
+                    nal_at(&prefix, codec).is_some()
+                }
+            };
+            if confirmed {
+                return Ok(Some(offset + at as u64));
+            }
+        }
+
+        // overlap the windows by a prefix
+        offset += len.saturating_sub(5).max(1) as u64;
+    }
+
+    Ok(None)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+    fn nal(header: u8, first_slice: bool, len: usize) -> Vec<u8> {
+        let mut nal = (len as u32 + 2).to_be_bytes().to_vec();
+        nal.push(header);
+        nal.push(if first_slice { 0x88 } else { 0x08 });
+        nal.extend(vec![0x42; len]);
+        nal
+    }
+
+    #[test]
+    fn recover_frames_around_other_chunks() {
+        let mut data = vec![];
+        // SPS, PPS and an IDR frame
+        data.extend(nal(0x67, false, 10));
+        data.extend(nal(0x68, false, 4));
+        data.extend(nal(0x65, true, 100));
+        // an audio chunk
+        data.extend([0xFF, 0xF1, 0x50, 0x80, 0x02, 0x1F, 0xFC].repeat(30));
+        // a frame of two slices, and another one
+        data.extend(nal(0x41, true, 50));
+        data.extend(nal(0x41, false, 50));
+        data.extend(nal(0x41, true, 60));
+        // cut short
+        data.extend(&nal(0x41, true, 60)[..20]);
+
+        let mut frames = vec![];
+        let end = data.len() as u64;
+        scan_frames(&mut Cursor::new(data), 0, end, Codec::H264, |frame| {
+            frames.push((frame.is_sync, frame.bytes.len()));
+            Ok(())
+        })
+        .unwrap();
+
+        assert_eq!(frames, vec![(true, 132), (false, 112), (false, 66)]);
+    }
+}
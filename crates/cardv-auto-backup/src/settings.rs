@@ -0,0 +1,82 @@
+use eframe::Storage;
+
+use crate::{
+    caption,
+    layout::{self, LayoutProfile},
+    queue::UploadOrder,
+    transcode::Transcoding,
+    trip::TripMerging,
+};
+
+const SETTINGS_STORAGE_KEY: &str = "SETTINGS";
+const DEFAULT_CONCURRENT_UPLOADS: usize = 2;
+
+/// User configuration that is persisted between runs
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+pub struct Settings {
+    /// User-defined layouts, these take precedence over the built-in ones
+    pub layouts: Vec<LayoutProfile>,
+    pub upload_order: UploadOrder,
+    /// How many files are uploaded at the same time
+    pub concurrent_uploads: usize,
+    /// The account has telegram premium, which raises the upload size limit
+    pub premium: bool,
+    /// The template every caption is rendered from
+    pub caption: String,
+    /// Generates the thumbnails of videos that don't come with one, disabled if empty
+    pub thumbnail_command: String,
+    /// Reply to every video with the GPS track the camera embedded in it
+    pub gps_tracks: bool,
+    pub transcoding: Transcoding,
+    /// Merge the consecutive segments of a drive into a single video
+    pub trips: TripMerging,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            layouts: vec![],
+            upload_order: UploadOrder::default(),
+            concurrent_uploads: DEFAULT_CONCURRENT_UPLOADS,
+            premium: false,
+            caption: caption::DEFAULT_TEMPLATE.into(),
+            thumbnail_command: String::new(),
+            gps_tracks: true,
+            transcoding: Transcoding::default(),
+            trips: TripMerging::default(),
+        }
+    }
+}
+
+impl Settings {
+    /// Load the settings from storage, falling back to the defaults
+    pub fn load(storage: Option<&dyn Storage>) -> Self {
+        storage
+            .and_then(|storage| storage.get_string(SETTINGS_STORAGE_KEY))
+            .and_then(|settings| serde_json::from_str(&settings).ok())
+            .unwrap_or_default()
+    }
+
+    pub fn save(&self, storage: &mut dyn Storage) {
+        storage.set_string(
+            SETTINGS_STORAGE_KEY,
+            serde_json::to_string(self).expect("serializing into string should never fail"),
+        );
+    }
+
+    pub fn thumbnail_command(&self) -> Option<String> {
+        Some(self.thumbnail_command.trim())
+            .filter(|command| !command.is_empty())
+            .map(String::from)
+    }
+
+    /// All the layouts to match an inserted card against, in order
+    pub fn layout_profiles(&self) -> Vec<LayoutProfile> {
+        self.layouts
+            .iter()
+            .cloned()
+            .chain(layout::builtin_profiles())
+            .collect()
+    }
+}
@@ -0,0 +1,251 @@
+//! Lossless splitting of mp4 files that are too big to upload in one piece
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use mp4::{Mp4Sample, TrackType};
+
+use crate::mp4_file::{self, impl_from_mp4_err, Mp4Err, Mp4Output};
+
+/// Room left in every part for the moov box, and for the
+/// group of pictures that overshoots the target size
+const HEADROOM: u64 = 64 * 1024 * 1024;
+
+#[derive(thiserror::Error, Debug)]
+pub enum SplitErr {
+    #[error(transparent)]
+    File(#[from] Mp4Err),
+
+    #[error("the mp4 has no video track to split at")]
+    NoVideoTrack,
+}
+
+impl_from_mp4_err!(SplitErr);
+
+/// Splits an mp4 at keyframes into playable parts no bigger than `max_size`
+///
+/// the parts are written into `out_dir` and returned in order. tracks the
+/// mp4 crate can't write (e.g. GPS data) are dropped, and h265 video isn't
+/// split at all, see [`mp4_file::ensure_copyable`].
+pub fn split_mp4(path: &Path, max_size: u64, out_dir: &Path) -> Result<Vec<PathBuf>, SplitErr> {
+    let mut parts = vec![];
+    let res = write_parts(path, max_size, out_dir, &mut parts);
+    if res.is_err() {
+        for part in &parts {
+            let _ = fs::remove_file(part);
+        }
+    }
+
+    res.map(|_| parts)
+}
+
+fn write_parts(
+    path: &Path,
+    max_size: u64,
+    out_dir: &Path,
+    parts: &mut Vec<PathBuf>,
+) -> Result<(), SplitErr> {
+    let mut mp4 = mp4_file::open(path)?;
+    let size = mp4.size();
+
+    // aim for equally sized parts rather than a tiny last one
+    let count = size.div_ceil(max_size.saturating_sub(HEADROOM).max(1));
+    let target = size / count;
+
+    let mut ids = mp4.tracks().keys().copied().collect::<Vec<_>>();
+    ids.sort_unstable();
+    for id in &ids {
+        mp4_file::ensure_copyable(&mp4.tracks()[id])?;
+    }
+    // the tracks we are able to copy, in the order they're written
+    let mut tracks = vec![];
+    for id in ids {
+        match mp4_file::track_config(&mp4.tracks()[&id]) {
+            Ok(config) => tracks.push((id, config, mp4.sample_count(id)?)),
+            Err(err) => tracing::warn!(?path, "dropping track {id} from the parts: {err}"),
+        }
+    }
+    let video = tracks
+        .iter()
+        .position(|(_, config, _)| config.track_type == TrackType::Video)
+        .ok_or(SplitErr::NoVideoTrack)?;
+
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+
+    // the next sample to read from every track (samples are 1-based)
+    let mut cursors = vec![1; tracks.len()];
+    // the keyframe that starts the next part
+    let mut pending: Option<Mp4Sample> = None;
+    loop {
+        let part = out_dir.join(format!("{stem}.part{}.mp4", parts.len() + 1));
+        parts.push(part.clone());
+        let mut writer =
+            Mp4Output::create(&part, &mp4, tracks.iter().map(|(_, config, _)| config))?;
+
+        let (video_id, video_config, video_count) = &tracks[video];
+        let mut written = 0;
+        if let Some(sample) = pending.take() {
+            written += sample.bytes.len() as u64;
+            writer.write_sample(video, &sample)?;
+        }
+        let mut cut = None;
+        while cursors[video] <= *video_count {
+            let sample = mp4.read_sample(*video_id, cursors[video])?;
+            cursors[video] += 1;
+            let Some(sample) = sample else {
+                continue;
+            };
+
+            if sample.is_sync && written >= target {
+                cut = Some(sample.start_time as f64 / video_config.timescale as f64);
+                pending = Some(sample);
+                break;
+            }
+            written += sample.bytes.len() as u64;
+            writer.write_sample(video, &sample)?;
+        }
+
+        // the rest of the tracks, up to the same point in time
+        for (idx, (id, config, count)) in tracks.iter().enumerate() {
+            if idx == video {
+                continue;
+            }
+
+            while cursors[idx] <= *count {
+                let Some(sample) = mp4.read_sample(*id, cursors[idx])? else {
+                    cursors[idx] += 1;
+                    continue;
+                };
+                let time = sample.start_time as f64 / config.timescale as f64;
+                if cut.is_some_and(|cut| time >= cut) {
+                    break;
+                }
+
+                cursors[idx] += 1;
+                writer.write_sample(idx, &sample)?;
+            }
+        }
+
+        writer.finish()?;
+
+        if pending.is_none() {
+            return Ok(());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        fs::File,
+        io::{BufWriter, Write},
+    };
+
+    use mp4::{AvcConfig, HevcConfig, MediaConfig, Mp4Config, Mp4Writer, TrackConfig};
+
+    use super::*;
+
+    const FRAMES: u32 = 100;
+    /// A keyframe every this many frames
+    const GOP: u32 = 10;
+    const FRAME_SIZE: usize = 1000;
+
+    /// A frame that tells its position
+    fn frame(idx: u32) -> Vec<u8> {
+        let mut frame = idx.to_be_bytes().to_vec();
+        frame.resize(FRAME_SIZE, 0x42);
+        frame
+    }
+
+    fn write_video(path: &Path, media_conf: MediaConfig) {
+        let config = Mp4Config {
+            major_brand: str::parse("isom").unwrap(),
+            minor_version: 512,
+            compatible_brands: vec![str::parse("isom").unwrap(), str::parse("avc1").unwrap()],
+            timescale: 1000,
+        };
+        let mut writer =
+            Mp4Writer::write_start(BufWriter::new(File::create(path).unwrap()), &config).unwrap();
+        writer
+            .add_track(&TrackConfig {
+                track_type: TrackType::Video,
+                timescale: 1000,
+                language: "und".into(),
+                media_conf,
+            })
+            .unwrap();
+        for idx in 0..FRAMES {
+            writer
+                .write_sample(
+                    1,
+                    &Mp4Sample {
+                        start_time: u64::from(idx) * 40,
+                        duration: 40,
+                        rendering_offset: 0,
+                        is_sync: idx % GOP == 0,
+                        bytes: frame(idx).into(),
+                    },
+                )
+                .unwrap();
+        }
+        writer.write_end().unwrap();
+        writer.into_writer().flush().unwrap();
+    }
+
+    #[test]
+    fn split_at_keyframes() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("clip.mp4");
+        write_video(
+            &path,
+            MediaConfig::AvcConfig(AvcConfig {
+                width: 320,
+                height: 240,
+                seq_param_set: vec![0x67, 0x42, 0xC0, 0x1E, 0xD9, 0x01, 0x41, 0xFB],
+                pic_param_set: vec![0x68, 0xCE, 0x3C, 0x80],
+            }),
+        );
+
+        // room for about a third of the frames in every part
+        let share = u64::from(FRAMES) * FRAME_SIZE as u64 / 3;
+        let max_size = HEADROOM + share;
+        let parts = split_mp4(&path, max_size, dir.path()).unwrap();
+        assert!(parts.len() > 1);
+
+        let mut frames = vec![];
+        for part in &parts {
+            let mut mp4 = mp4_file::open(part).unwrap();
+            assert!(mp4.size() <= max_size);
+
+            let mut written = 0;
+            for sample_id in 1..=mp4.sample_count(1).unwrap() {
+                let sample = mp4.read_sample(1, sample_id).unwrap().unwrap();
+                if sample_id == 1 {
+                    assert!(sample.is_sync, "{part:?} doesn't start with a keyframe");
+                }
+                written += sample.bytes.len() as u64;
+                frames.push(sample.bytes.to_vec());
+            }
+            // a part only overshoots its share to finish a group of pictures
+            assert!(written <= share + u64::from(GOP) * FRAME_SIZE as u64);
+        }
+        assert_eq!(frames, (0..FRAMES).map(frame).collect::<Vec<_>>());
+
+        // the parts of an h265 video would lose its parameter sets
+        let hevc = dir.path().join("hevc.mp4");
+        write_video(
+            &hevc,
+            MediaConfig::HevcConfig(HevcConfig {
+                width: 320,
+                height: 240,
+            }),
+        );
+        let out_dir = dir.path().join("hevc");
+        fs::create_dir(&out_dir).unwrap();
+        let res = split_mp4(&hevc, max_size, &out_dir);
+        assert!(matches!(res, Err(SplitErr::File(Mp4Err::Hevc))));
+        assert_eq!(fs::read_dir(&out_dir).unwrap().count(), 0);
+    }
+}
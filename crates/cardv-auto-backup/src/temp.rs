@@ -0,0 +1,23 @@
+//! Scratch files of the jobs in progress (repaired, merged, transcoded copies...)
+
+use std::{
+    path::{Path, PathBuf},
+    process,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+/// A path in the app's temp directory, named after the file it's made from
+///
+/// every call returns another path, so concurrent jobs never share a file,
+/// even if they work on the same file or on copies with the same content.
+pub fn unique_path(source: &Path, extension: &str) -> PathBuf {
+    static NEXT: AtomicUsize = AtomicUsize::new(0);
+
+    std::env::temp_dir().join(crate::APP_ID).join(format!(
+        "{}.{}.{}.{extension}",
+        source.file_stem().unwrap_or_default().to_string_lossy(),
+        // another instance of the app may be running
+        process::id(),
+        NEXT.fetch_add(1, Ordering::Relaxed)
+    ))
+}
@@ -1,16 +1,60 @@
-use std::{fmt::Debug, path::Path, time::Duration};
+use std::{
+    fmt::Debug,
+    future::Future,
+    io,
+    path::{Path, PathBuf},
+    pin::Pin,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex, RwLock,
+    },
+    task::{Context, Poll},
+    time::{Duration, Instant, SystemTime},
+};
 
 use grammers_client::{
-    types::{Attribute, PackedChat},
-    Client, Config, InputMessage, Update,
+    types::{media::Uploaded, Attribute, PackedChat},
+    Client, Config, InputMedia, InputMessage, Update,
 };
 use grammers_session::Session;
+use grammers_tl_types as tl;
+use tokio::io::{AsyncRead, ReadBuf};
+
+use crate::{
+    destination::{ConnectionEvent, Destination, DestinationErr, ErrorClass, MediaKind},
+    mp4_file::Mp4Err,
+    settings::Settings,
+    video::{VideoErr, VideoInfo},
+};
 
 const API_ID: i32 = 6;
 const API_HASH: &str = "eb06d4abfb49dc3eeb1aeb98ae0f581e";
 
 const IDENTIFY_MESSAGE: &str = "this-message-is-used-by-the-bot-to-get-the-channel-hash";
 
+/// How many flood waits are sat out for a single request before giving up
+const MAX_FLOOD_WAITS: u32 = 5;
+/// The fastest pace of requests (per second), we start from it and back off on flood waits
+const MAX_RATE: f64 = 1.0;
+const MIN_RATE: f64 = 1.0 / 60.0;
+/// How much the pace recovers after every successful request
+const RATE_RECOVERY: f64 = 0.05;
+/// How many requests can be sent in a burst
+const BURST: f64 = 3.0;
+
+/// How long the connection may stay idle before it's pinged
+const KEEPALIVE_INTERVAL: Duration = Duration::from_secs(60);
+const PING_TIMEOUT: Duration = Duration::from_secs(10);
+/// How many times we try to reconnect before giving up
+const MAX_RECONNECTS: u32 = 5;
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+
+/// The biggest file telegram accepts
+const MAX_FILE_SIZE: u64 = 2000 * 1024 * 1024;
+const MAX_PREMIUM_FILE_SIZE: u64 = 4000 * 1024 * 1024;
+/// The longest caption telegram accepts, premium accounts may send longer ones
+const MAX_CAPTION_LEN: usize = 1024;
+
 #[derive(thiserror::Error, Debug)]
 pub enum BotErr {
     #[error("failed to connect to telegram servers")]
@@ -36,6 +80,94 @@ pub enum BotErr {
 
     #[error("failed to extract the video attribute from path")]
     NoVideoAttribute,
+
+    #[error("the request has timed out")]
+    Timeout,
+}
+
+impl BotErr {
+    /// How long telegram asked us to wait (FLOOD_WAIT_X), if it did
+    pub fn flood_wait(&self) -> Option<Duration> {
+        match self {
+            Self::Invocation(grammers_mtsender::InvocationError::Rpc(rpc))
+                if rpc.name.starts_with("FLOOD") && rpc.name.ends_with("WAIT") =>
+            {
+                Some(Duration::from_secs(rpc.value.unwrap_or(1).into()))
+            }
+            _ => None,
+        }
+    }
+
+    pub fn class(&self) -> ErrorClass {
+        use grammers_mtsender::InvocationError;
+
+        match self {
+            Self::Communication | Self::Timeout => ErrorClass::Retryable,
+            Self::Invocation(InvocationError::Rpc(rpc)) => match rpc.code {
+                // FLOOD_WAIT and friends
+                420 => ErrorClass::Retryable,
+                code if code >= 500 => ErrorClass::Retryable,
+                // the bot was logged out, banned or removed from the channel
+                401 | 403 => ErrorClass::Fatal,
+                _ if rpc.name == "CHANNEL_PRIVATE" || rpc.name == "CHAT_WRITE_FORBIDDEN" => {
+                    ErrorClass::Fatal
+                }
+                // e.g. an invalid or oversized file
+                _ => ErrorClass::Skippable,
+            },
+            // the connection was dropped or the response couldn't be read
+            Self::Invocation(_) => ErrorClass::Retryable,
+            Self::BadSession(_)
+            | Self::BadAuth(_)
+            | Self::CorruptedTargetChat
+            | Self::NoTargetChat => ErrorClass::Fatal,
+            Self::Io(_) | Self::NoVideoAttribute => ErrorClass::Skippable,
+        }
+    }
+
+    /// Whether the error suggests the connection itself is broken
+    fn is_connection_error(&self) -> bool {
+        match self {
+            Self::Communication | Self::Timeout => true,
+            Self::Invocation(grammers_mtsender::InvocationError::Rpc(_)) => false,
+            Self::Invocation(_) => true,
+            _ => false,
+        }
+    }
+}
+
+impl From<BotErr> for DestinationErr {
+    fn from(err: BotErr) -> Self {
+        Self::Backend {
+            class: err.class(),
+            source: err.into(),
+        }
+    }
+}
+
+/// A file that was uploaded to telegram, but not sent to the channel yet
+///
+/// this lets the (slow) uploads run concurrently, while the
+/// messages themselves are still sent in order.
+#[derive(Debug, Clone)]
+pub enum UploadedMedia {
+    Video {
+        video: Uploaded,
+        info: VideoInfo,
+        thumbnail: Option<Uploaded>,
+    },
+    Photo(Uploaded),
+    /// Sent as a plain file, without a preview
+    Document(Uploaded),
+}
+
+impl UploadedMedia {
+    pub fn video_info(&self) -> Option<&VideoInfo> {
+        match self {
+            Self::Video { info, .. } => Some(info),
+            _ => None,
+        }
+    }
 }
 
 #[derive(Debug, serde::Serialize, serde::Deserialize)]
@@ -46,8 +178,161 @@ pub struct PackedBot {
 
 #[derive(Debug)]
 pub struct Bot {
-    client: Client,
+    connection: Connection,
     target_channel: PackedChat,
+    limiter: RateLimiter,
+}
+
+/// Keeps a single client alive, and only replaces it once it's found dead
+#[derive(Debug)]
+struct Connection {
+    client: RwLock<Client>,
+    last_active: Mutex<Instant>,
+    /// A request has failed in a way that suggests the connection is broken
+    suspect: AtomicBool,
+    /// Makes sure only one task checks (and re-creates) the connection at a time
+    checking: tokio::sync::Mutex<()>,
+}
+
+impl Connection {
+    fn new(client: Client) -> Self {
+        Self {
+            client: RwLock::new(client),
+            last_active: Mutex::new(Instant::now()),
+            suspect: AtomicBool::new(false),
+            checking: Default::default(),
+        }
+    }
+
+    fn client(&self) -> Client {
+        self.client.read().unwrap().clone()
+    }
+
+    fn mark_alive(&self) {
+        *self.last_active.lock().unwrap() = Instant::now();
+        self.suspect.store(false, Ordering::Relaxed);
+    }
+
+    fn mark_suspect(&self) {
+        self.suspect.store(true, Ordering::Relaxed);
+    }
+
+    fn needs_check(&self) -> bool {
+        self.suspect.load(Ordering::Relaxed)
+            || self.last_active.lock().unwrap().elapsed() >= KEEPALIVE_INTERVAL
+    }
+
+    async fn ping(&self) -> Result<(), BotErr> {
+        let ping_id = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos() as i64;
+
+        tokio::time::timeout(
+            PING_TIMEOUT,
+            self.client().invoke(&tl::functions::Ping { ping_id }),
+        )
+        .await
+        .map_err(|_| BotErr::Timeout)??;
+
+        Ok(())
+    }
+}
+
+/// Reports how many bytes were read from the inner reader
+struct ProgressReader<'a, R, F> {
+    inner: R,
+    read: u64,
+    on_progress: &'a F,
+}
+
+impl<'a, R, F: Fn(u64)> ProgressReader<'a, R, F> {
+    fn new(inner: R, on_progress: &'a F) -> Self {
+        on_progress(0);
+        Self {
+            inner,
+            read: 0,
+            on_progress,
+        }
+    }
+}
+
+impl<R: AsyncRead + Unpin, F: Fn(u64)> AsyncRead for ProgressReader<'_, R, F> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let before = buf.filled().len();
+        let res = Pin::new(&mut self.inner).poll_read(cx, buf);
+        if let Poll::Ready(Ok(())) = res {
+            self.read += (buf.filled().len() - before) as u64;
+            (self.on_progress)(self.read);
+        }
+
+        res
+    }
+}
+
+/// An adaptive token bucket that paces the requests sent to telegram
+///
+/// the rate is halved on every flood wait, and slowly
+/// recovers with every request that goes through.
+#[derive(Debug)]
+struct RateLimiter {
+    bucket: tokio::sync::Mutex<Bucket>,
+}
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    /// Tokens per second
+    rate: f64,
+    /// On tokio's clock, so it follows the time when it's paused
+    refilled_at: tokio::time::Instant,
+}
+
+impl RateLimiter {
+    fn new() -> Self {
+        Self {
+            bucket: tokio::sync::Mutex::new(Bucket {
+                tokens: BURST,
+                rate: MAX_RATE,
+                refilled_at: tokio::time::Instant::now(),
+            }),
+        }
+    }
+
+    /// Waits until a request may be sent
+    async fn acquire(&self) {
+        let mut bucket = self.bucket.lock().await;
+
+        let now = tokio::time::Instant::now();
+        let elapsed = now.duration_since(bucket.refilled_at).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * bucket.rate).min(BURST);
+        bucket.refilled_at = now;
+
+        if bucket.tokens < 1.0 {
+            let missing = 1.0 - bucket.tokens;
+            // holding the lock makes the other requests queue up behind us
+            tokio::time::sleep(Duration::from_secs_f64(missing / bucket.rate)).await;
+            bucket.tokens = 1.0;
+            bucket.refilled_at = tokio::time::Instant::now();
+        }
+        bucket.tokens -= 1.0;
+    }
+
+    async fn on_success(&self) {
+        let mut bucket = self.bucket.lock().await;
+        bucket.rate = (bucket.rate + RATE_RECOVERY).min(MAX_RATE);
+    }
+
+    async fn on_flood_wait(&self) {
+        let mut bucket = self.bucket.lock().await;
+        bucket.rate = (bucket.rate / 2.0).max(MIN_RATE);
+        bucket.tokens = 0.0;
+        tracing::debug!("slowing down to {:.3} requests per second", bucket.rate);
+    }
 }
 
 impl Bot {
@@ -72,8 +357,9 @@ impl Bot {
         let target_channel = find_channel(&client, token, target_channel).await?;
 
         Ok(Self {
-            client,
+            connection: Connection::new(client),
             target_channel,
+            limiter: RateLimiter::new(),
         })
     }
 
@@ -82,56 +368,453 @@ impl Bot {
     pub async fn from_packed(packed: PackedBot) -> Result<Self, BotErr> {
         let target_channel =
             PackedChat::from_bytes(&packed.target_chat).map_err(|_| BotErr::CorruptedTargetChat)?;
-        let session = Session::load(&packed.session)?;
-
-        let client = Client::connect(Config {
-            session,
-            api_id: API_ID,
-            api_hash: API_HASH.into(),
-            params: Default::default(),
-        })
-        .await?;
-
-        // we'll never pack an unauthorized bot
-        debug_assert!(client.is_authorized().await?);
+        let client = connect(&packed.session).await?;
 
         Ok(Self {
-            client,
+            connection: Connection::new(client),
             target_channel,
+            limiter: RateLimiter::new(),
         })
     }
 
     /// Pack the bot into a serializable structure
     pub fn packed(&self) -> PackedBot {
         PackedBot {
-            session: self.client.session().save(),
+            session: self.connection.client().session().save(),
             target_chat: self.target_channel.to_bytes().into(),
         }
     }
 
-    /// Uploads an mp4 video to the target channel
-    #[tracing::instrument]
-    pub async fn upload_mp4(
+    /// Uploads an mp4 video, without sending it to the channel yet
+    ///
+    /// `on_progress` is called with the amount of bytes sent so far,
+    /// and starts over from zero if the upload has to be restarted.
+    /// the thumbnail, if any, must be a JPEG telegram accepts (see [`crate::thumbnail`]).
+    #[tracing::instrument(skip(thumbnail, on_progress))]
+    async fn upload_mp4(
         &self,
         path: impl AsRef<Path> + Debug + Clone + Send + 'static,
-    ) -> Result<(), BotErr> {
-        let attribute = get_mp4_attribute(path.clone()).await?;
-        let video = self.client.upload_file(path.clone()).await?;
-
-        self.client
-            .send_message(
-                self.target_channel,
-                InputMessage::default()
+        thumbnail: Option<&[u8]>,
+        on_progress: impl Fn(u64) + Send + Sync,
+    ) -> Result<UploadedMedia, BotErr> {
+        let info = video_info(path.clone()).await?;
+        let video = self.stream_file(path, on_progress).await?;
+
+        // a missing thumbnail isn't worth failing the upload over
+        let thumbnail = match thumbnail {
+            Some(jpeg) => self
+                .paced(|| {
+                    let client = self.connection.client();
+                    async move {
+                        client
+                            .upload_stream(&mut &*jpeg, jpeg.len(), "thumbnail.jpg".into())
+                            .await
+                    }
+                })
+                .await
+                .map_err(|err| tracing::warn!("failed to upload the thumbnail: {err}"))
+                .ok(),
+            None => None,
+        };
+
+        Ok(UploadedMedia::Video {
+            video,
+            info,
+            thumbnail,
+        })
+    }
+
+    /// Uploads a file that telegram can't play (e.g. a corrupted clip), to be sent as a plain document
+    #[tracing::instrument(skip(on_progress))]
+    async fn upload_document(
+        &self,
+        path: impl AsRef<Path> + Debug + Clone + Send + 'static,
+        on_progress: impl Fn(u64) + Send + Sync,
+    ) -> Result<UploadedMedia, BotErr> {
+        let document = self.stream_file(path, on_progress).await?;
+
+        Ok(UploadedMedia::Document(document))
+    }
+
+    /// Streams a file to telegram, reporting the progress
+    async fn stream_file(
+        &self,
+        path: impl AsRef<Path> + Clone,
+        on_progress: impl Fn(u64) + Send + Sync,
+    ) -> Result<Uploaded, BotErr> {
+        let name = path
+            .as_ref()
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_default();
+        self.paced(|| {
+            let client = self.connection.client();
+            let path = path.clone();
+            let name = name.clone();
+            let on_progress = &on_progress;
+            async move {
+                let file = tokio::fs::File::open(path).await?;
+                let size = file.metadata().await?.len() as usize;
+                let mut stream = ProgressReader::new(file, on_progress);
+                client.upload_stream(&mut stream, size, name).await
+            }
+        })
+        .await
+    }
+
+    /// Uploads a photo (e.g. a dashcam snapshot), without sending it to the channel yet
+    #[tracing::instrument]
+    async fn upload_photo(
+        &self,
+        path: impl AsRef<Path> + Debug + Send + 'static,
+    ) -> Result<UploadedMedia, BotErr> {
+        let photo = self
+            .paced(|| {
+                let client = self.connection.client();
+                let path = &path;
+                async move { client.upload_file(path).await }
+            })
+            .await?;
+
+        Ok(UploadedMedia::Photo(photo))
+    }
+
+    /// Sends a request through the rate limiter
+    ///
+    /// flood waits are sat out for exactly as long as telegram asks,
+    /// and then the request is sent again. the outcome of the request
+    /// also tells us whether the connection is still alive.
+    async fn paced<T, E, F>(&self, request: impl Fn() -> F) -> Result<T, BotErr>
+    where
+        F: Future<Output = Result<T, E>>,
+        E: Into<BotErr>,
+    {
+        let mut flood_waits = 0;
+        loop {
+            self.limiter.acquire().await;
+
+            match request().await.map_err(Into::into) {
+                Ok(res) => {
+                    self.connection.mark_alive();
+                    self.limiter.on_success().await;
+                    return Ok(res);
+                }
+                Err(err) if err.is_connection_error() => {
+                    self.connection.mark_suspect();
+                    return Err(err);
+                }
+                Err(err) => match err.flood_wait() {
+                    Some(wait) if flood_waits < MAX_FLOOD_WAITS => {
+                        flood_waits += 1;
+                        tracing::warn!("hit a flood wait, sleeping for {wait:?}");
+                        self.limiter.on_flood_wait().await;
+                        tokio::time::sleep(wait).await;
+                    }
+                    _ => return Err(err),
+                },
+            }
+        }
+    }
+}
+
+impl Destination for Bot {
+    type Media = UploadedMedia;
+
+    fn video_info(media: &UploadedMedia) -> Option<&VideoInfo> {
+        media.video_info()
+    }
+
+    fn max_file_size(&self, settings: &Settings) -> u64 {
+        if settings.premium {
+            MAX_PREMIUM_FILE_SIZE
+        } else {
+            MAX_FILE_SIZE
+        }
+    }
+
+    fn max_caption_len(&self) -> usize {
+        MAX_CAPTION_LEN
+    }
+
+    fn connection_failed(&self) {
+        self.connection.mark_suspect();
+    }
+
+    /// The server is only pinged if the connection was idle for a while,
+    /// or if a previous request failed because of a connection issue.
+    async fn ensure_connected(
+        &self,
+        on_event: impl Fn(ConnectionEvent) + Send,
+    ) -> Result<(), DestinationErr> {
+        if !self.connection.needs_check() {
+            return Ok(());
+        }
+
+        let _checking = self.connection.checking.lock().await;
+        // another task may have checked it while we waited
+        if !self.connection.needs_check() {
+            return Ok(());
+        }
+
+        match self.connection.ping().await {
+            Ok(()) => {
+                self.connection.mark_alive();
+                return Ok(());
+            }
+            Err(err) => {
+                tracing::warn!("the connection is dead ({err}), reconnecting");
+                on_event(ConnectionEvent::Lost);
+            }
+        }
+
+        let session = self.connection.client().session().save();
+        let mut attempt = 0;
+        loop {
+            attempt += 1;
+            match connect(&session).await {
+                Ok(client) => {
+                    *self.connection.client.write().unwrap() = client;
+                    self.connection.mark_alive();
+                    tracing::info!("reconnected after {attempt} attempts");
+                    on_event(ConnectionEvent::Restored);
+                    return Ok(());
+                }
+                Err(err) if err.class() == ErrorClass::Retryable && attempt < MAX_RECONNECTS => {
+                    tracing::warn!("reconnect attempt {attempt} failed: {err}");
+                    tokio::time::sleep(RECONNECT_DELAY * attempt).await;
+                }
+                Err(err) => return Err(err.into()),
+            }
+        }
+    }
+
+    async fn upload(
+        &self,
+        path: PathBuf,
+        kind: MediaKind<'_>,
+        on_progress: impl Fn(u64) + Send + Sync,
+    ) -> Result<UploadedMedia, DestinationErr> {
+        let media = match kind {
+            MediaKind::Video { thumbnail } => self.upload_mp4(path, thumbnail, on_progress).await?,
+            MediaKind::Photo => self.upload_photo(path).await?,
+            MediaKind::Document => self.upload_document(path, on_progress).await?,
+        };
+
+        Ok(media)
+    }
+
+    #[tracing::instrument(skip(media))]
+    async fn send(
+        &self,
+        media: &UploadedMedia,
+        caption: String,
+        reply_to: Option<i32>,
+    ) -> Result<i32, DestinationErr> {
+        let message = match media.clone() {
+            UploadedMedia::Video {
+                video,
+                info,
+                thumbnail,
+            } => {
+                let message = InputMessage::text(caption)
                     .mime_type("video/mp4")
                     .document(video)
-                    .attribute(attribute),
-            )
+                    .attribute(video_attribute(&info));
+                match thumbnail {
+                    Some(thumbnail) => message.thumbnail(thumbnail),
+                    None => message,
+                }
+            }
+            UploadedMedia::Photo(photo) => InputMessage::text(caption).photo(photo),
+            UploadedMedia::Document(document) => InputMessage::text(caption)
+                .mime_type("application/octet-stream")
+                .document(document),
+        }
+        .reply_to(reply_to);
+        let message = self
+            .paced(|| {
+                let client = self.connection.client();
+                let message = message.clone();
+                async move { client.send_message(self.target_channel, message).await }
+            })
+            .await?;
+
+        Ok(message.id())
+    }
+
+    #[tracing::instrument(skip(media))]
+    async fn send_album(
+        &self,
+        media: &[&UploadedMedia],
+        caption: String,
+    ) -> Result<Vec<Option<i32>>, DestinationErr> {
+        let album = media
+            .iter()
+            .enumerate()
+            .map(|(idx, media)| {
+                let caption = if idx == 0 { caption.as_str() } else { "" };
+                match (*media).clone() {
+                    UploadedMedia::Video {
+                        video,
+                        info,
+                        thumbnail,
+                    } => {
+                        let media = InputMedia::caption(caption)
+                            .mime_type("video/mp4")
+                            .document(video)
+                            .attribute(video_attribute(&info));
+                        match thumbnail {
+                            Some(thumbnail) => media.thumbnail(thumbnail),
+                            None => media,
+                        }
+                    }
+                    UploadedMedia::Photo(photo) => InputMedia::caption(caption).photo(photo),
+                    UploadedMedia::Document(document) => InputMedia::caption(caption)
+                        .mime_type("application/octet-stream")
+                        .document(document),
+                }
+            })
+            .collect::<Vec<_>>();
+        let messages = self
+            .paced(|| {
+                let client = self.connection.client();
+                let album = album.clone();
+                async move { client.send_album(self.target_channel, album).await }
+            })
             .await?;
 
+        Ok(messages
+            .into_iter()
+            .map(|message| message.map(|message| message.id()))
+            .collect())
+    }
+
+    #[tracing::instrument(skip(bytes))]
+    async fn send_document(
+        &self,
+        name: String,
+        mime_type: &str,
+        bytes: &[u8],
+        reply_to: Option<i32>,
+    ) -> Result<i32, DestinationErr> {
+        let document = self
+            .paced(|| {
+                let client = self.connection.client();
+                let name = name.clone();
+                async move { client.upload_stream(&mut &*bytes, bytes.len(), name).await }
+            })
+            .await?;
+        let message = InputMessage::text("")
+            .mime_type(mime_type)
+            .document(document)
+            .reply_to(reply_to);
+        let message = self
+            .paced(|| {
+                let client = self.connection.client();
+                let message = message.clone();
+                async move { client.send_message(self.target_channel, message).await }
+            })
+            .await?;
+
+        Ok(message.id())
+    }
+
+    #[tracing::instrument]
+    async fn send_location(
+        &self,
+        latitude: f64,
+        longitude: f64,
+        reply_to: Option<i32>,
+    ) -> Result<(), DestinationErr> {
+        let random_id = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos() as i64;
+        // the client has no builder for geo points, so the raw request is sent
+        let request = tl::functions::messages::SendMedia {
+            silent: true,
+            background: false,
+            clear_draft: false,
+            noforwards: false,
+            update_stickersets_order: false,
+            invert_media: false,
+            peer: self.target_channel.to_input_peer(),
+            reply_to: reply_to.map(|reply_to_msg_id| {
+                tl::types::InputReplyToMessage {
+                    reply_to_msg_id,
+                    top_msg_id: None,
+                    reply_to_peer_id: None,
+                    quote_text: None,
+                    quote_entities: None,
+                    quote_offset: None,
+                }
+                .into()
+            }),
+            media: tl::types::InputMediaGeoPoint {
+                geo_point: tl::types::InputGeoPoint {
+                    lat: latitude,
+                    long: longitude,
+                    accuracy_radius: None,
+                }
+                .into(),
+            }
+            .into(),
+            message: String::new(),
+            random_id,
+            reply_markup: None,
+            entities: None,
+            schedule_date: None,
+            send_as: None,
+        };
+        self.paced(|| {
+            let client = self.connection.client();
+            let request = request.clone();
+            async move { client.invoke(&request).await }
+        })
+        .await?;
+
+        Ok(())
+    }
+
+    #[tracing::instrument]
+    async fn exists(&self, id: i32) -> Result<bool, DestinationErr> {
+        let messages = self
+            .paced(|| {
+                let client = self.connection.client();
+                async move { client.get_messages_by_id(self.target_channel, &[id]).await }
+            })
+            .await?;
+
+        Ok(messages.into_iter().any(|message| message.is_some()))
+    }
+
+    #[tracing::instrument]
+    async fn delete(&self, ids: &[i32]) -> Result<(), DestinationErr> {
+        self.paced(|| {
+            let client = self.connection.client();
+            async move { client.delete_messages(self.target_channel, ids).await }
+        })
+        .await?;
+
         Ok(())
     }
 }
 
+async fn connect(session: &[u8]) -> Result<Client, BotErr> {
+    let client = Client::connect(Config {
+        session: Session::load(session)?,
+        api_id: API_ID,
+        api_hash: API_HASH.into(),
+        params: Default::default(),
+    })
+    .await?;
+
+    // we'll never pack an unauthorized bot
+    debug_assert!(client.is_authorized().await?);
+
+    Ok(client)
+}
+
 #[tracing::instrument]
 async fn find_channel(client: &Client, token: &str, channel: i64) -> Result<PackedChat, BotErr> {
     // The bot can't find the chat has from its chat_id, so we need to use
@@ -169,34 +852,149 @@ async fn find_channel(client: &Client, token: &str, channel: i64) -> Result<Pack
 }
 
 #[tracing::instrument]
-async fn get_mp4_attribute(
-    path: impl AsRef<Path> + Debug + Send + 'static,
-) -> Result<Attribute, BotErr> {
-    use std::{fs, io};
-
+async fn video_info(path: impl AsRef<Path> + Debug + Send + 'static) -> Result<VideoInfo, BotErr> {
     tokio::task::spawn_blocking(move || {
-        let file = fs::File::open(path)?;
-        let size = file.metadata()?.len();
-        let reader = io::BufReader::new(file);
-
-        let mp4 = mp4::Mp4Reader::read_header(reader, size).map_err(|err| {
-            tracing::error!(?err);
-            BotErr::NoVideoAttribute
-        })?;
-        let track = mp4
-            .tracks()
-            .values()
-            .next()
-            .ok_or(BotErr::NoVideoAttribute)?;
-
-        Ok::<Attribute, BotErr>(Attribute::Video {
-            round_message: false,
-            supports_streaming: true,
-            duration: track.duration(),
-            w: track.width() as i32,
-            h: track.height() as i32,
+        VideoInfo::read(path.as_ref()).map_err(|err| match err {
+            VideoErr::File(Mp4Err::Io(err)) => BotErr::Io(err),
+            err => {
+                tracing::error!(?err);
+                BotErr::NoVideoAttribute
+            }
         })
     })
     .await
     .map_err(|_| BotErr::NoVideoAttribute)?
 }
+
+/// Telegram shows the video as it is displayed, so the rotation is already applied
+fn video_attribute(info: &VideoInfo) -> Attribute {
+    Attribute::Video {
+        round_message: false,
+        supports_streaming: true,
+        duration: info.duration,
+        w: info.width.into(),
+        h: info.height.into(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{io, time::Duration};
+
+    use grammers_mtsender::{InvocationError, RpcError};
+    use tokio::time::Instant;
+
+    use super::{
+        BotErr, DestinationErr, ErrorClass, RateLimiter, BURST, MAX_RATE, MIN_RATE, RATE_RECOVERY,
+    };
+
+    fn rpc(code: i32, name: &str) -> BotErr {
+        rpc_with_value(code, name, None)
+    }
+
+    fn rpc_with_value(code: i32, name: &str, value: Option<u32>) -> BotErr {
+        BotErr::Invocation(InvocationError::Rpc(RpcError {
+            code,
+            name: name.into(),
+            value,
+            caused_by: None,
+        }))
+    }
+
+    #[test]
+    fn classify_errors() {
+        use ErrorClass::*;
+
+        assert_eq!(BotErr::Communication.class(), Retryable);
+        assert_eq!(BotErr::Timeout.class(), Retryable);
+        assert_eq!(
+            BotErr::Invocation(InvocationError::Dropped).class(),
+            Retryable
+        );
+        assert_eq!(rpc(420, "FLOOD_WAIT").class(), Retryable);
+        assert_eq!(rpc(500, "RPC_CALL_FAIL").class(), Retryable);
+
+        assert_eq!(rpc(400, "FILE_PARTS_INVALID").class(), Skippable);
+        assert_eq!(BotErr::Io(io::Error::other("gone")).class(), Skippable);
+        assert_eq!(BotErr::NoVideoAttribute.class(), Skippable);
+
+        assert_eq!(rpc(401, "AUTH_KEY_UNREGISTERED").class(), Fatal);
+        assert_eq!(rpc(403, "CHAT_WRITE_FORBIDDEN").class(), Fatal);
+        assert_eq!(rpc(400, "CHANNEL_PRIVATE").class(), Fatal);
+        assert_eq!(BotErr::NoTargetChat.class(), Fatal);
+        assert_eq!(BotErr::CorruptedTargetChat.class(), Fatal);
+
+        // the uploader only sees the destination's error, which keeps the class
+        let err = DestinationErr::from(rpc(401, "AUTH_KEY_UNREGISTERED"));
+        assert_eq!(err.class(), Fatal);
+    }
+
+    #[test]
+    fn parse_flood_wait() {
+        assert_eq!(
+            rpc_with_value(420, "FLOOD_WAIT", Some(30)).flood_wait(),
+            Some(Duration::from_secs(30))
+        );
+        assert_eq!(
+            rpc_with_value(420, "FLOOD_PREMIUM_WAIT", Some(5)).flood_wait(),
+            Some(Duration::from_secs(5))
+        );
+        // telegram always sends the duration, but a missing one shouldn't mean no wait
+        assert_eq!(
+            rpc(420, "FLOOD_WAIT").flood_wait(),
+            Some(Duration::from_secs(1))
+        );
+        assert_eq!(rpc(400, "FILE_PARTS_INVALID").flood_wait(), None);
+        assert_eq!(BotErr::Timeout.flood_wait(), None);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn refill_tokens() {
+        let limiter = RateLimiter::new();
+        let start = Instant::now();
+
+        // a burst goes through right away, and the rest are paced
+        for _ in 0..BURST as usize {
+            limiter.acquire().await;
+        }
+        assert_eq!(start.elapsed(), Duration::ZERO);
+        limiter.acquire().await;
+        assert_eq!(start.elapsed(), Duration::from_secs_f64(1.0 / MAX_RATE));
+
+        // an idle limiter refills up to a single burst
+        tokio::time::sleep(Duration::from_secs(60)).await;
+        let start = Instant::now();
+        for _ in 0..BURST as usize {
+            limiter.acquire().await;
+        }
+        assert_eq!(start.elapsed(), Duration::ZERO);
+        limiter.acquire().await;
+        assert_eq!(start.elapsed(), Duration::from_secs_f64(1.0 / MAX_RATE));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn slow_down_after_flood_wait() {
+        let limiter = RateLimiter::new();
+
+        // the bucket is emptied, and refills at half the pace
+        limiter.on_flood_wait().await;
+        let start = Instant::now();
+        limiter.acquire().await;
+        assert_eq!(start.elapsed(), Duration::from_secs_f64(2.0 / MAX_RATE));
+
+        // every request that goes through recovers some of the pace
+        limiter.on_success().await;
+        let rate = limiter.bucket.lock().await.rate;
+        assert!((rate - (MAX_RATE / 2.0 + RATE_RECOVERY)).abs() < 1e-9);
+        for _ in 0..100 {
+            limiter.on_success().await;
+        }
+        assert_eq!(limiter.bucket.lock().await.rate, MAX_RATE);
+
+        // but the requests never stop altogether
+        for _ in 0..100 {
+            limiter.on_flood_wait().await;
+        }
+        assert_eq!(limiter.bucket.lock().await.rate, MIN_RATE);
+    }
+}
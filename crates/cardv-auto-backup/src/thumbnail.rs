@@ -0,0 +1,138 @@
+//! JPEG thumbnails for the uploaded videos
+//!
+//! telegram only shows thumbnails that are JPEGs of at most 200 KB and 320x320,
+//! and we can't decode video ourselves, so the thumbnail is taken from:
+//! 1. the cover art some cameras embed in the mp4
+//! 2. a `.THM` file next to the clip, as some cameras write one
+//! 3. a user-configured decoder command, e.g. ffmpeg
+
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use mp4::Metadata;
+
+use crate::{command, mp4_file, temp};
+
+const MAX_THUMBNAIL_SIZE: usize = 200 * 1024;
+const MAX_THUMBNAIL_SIDE: u16 = 320;
+/// How long the decoder command may run before it's killed
+const COMMAND_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// A suggestion for the decoder command, shown as a hint in the settings
+pub const EXAMPLE_COMMAND: &str =
+    "ffmpeg -y -loglevel error -i {input} -frames:v 1 -vf scale=320:320:force_original_aspect_ratio=decrease {output}";
+
+/// Finds or generates a thumbnail telegram accepts, `None` if there's none
+///
+/// the command is split on whitespace, and `{input}` and `{output}` are replaced
+/// by the clip and by the path the JPEG should be written to.
+pub async fn thumbnail(path: PathBuf, command: Option<String>) -> Option<Vec<u8>> {
+    tokio::task::spawn_blocking(move || {
+        embedded(&path)
+            .or_else(|| sidecar(&path))
+            .or_else(|| match command.as_deref() {
+                Some(command) => decode(&path, command)
+                    .map_err(|err| tracing::warn!(?path, "thumbnail command failed: {err}"))
+                    .ok()
+                    .filter(|jpeg| usable(jpeg)),
+                None => None,
+            })
+    })
+    .await
+    .ok()
+    .flatten()
+}
+
+fn embedded(path: &Path) -> Option<Vec<u8>> {
+    let mp4 = mp4_file::open(path).ok()?;
+
+    mp4.metadata()
+        .poster()
+        .filter(|jpeg| usable(jpeg))
+        .map(|jpeg| jpeg.to_vec())
+}
+
+fn sidecar(path: &Path) -> Option<Vec<u8>> {
+    ["THM", "thm"]
+        .into_iter()
+        .filter_map(|extension| fs::read(path.with_extension(extension)).ok())
+        .find(|jpeg| usable(jpeg))
+}
+
+fn decode(path: &Path, template: &str) -> io::Result<Vec<u8>> {
+    // the cameras' channels may use the same names in different folders
+    let output = temp::unique_path(path, "jpg");
+    fs::create_dir_all(output.parent().unwrap())?;
+
+    command::run(
+        template,
+        &[("input", path.as_os_str()), ("output", output.as_os_str())],
+        COMMAND_TIMEOUT,
+    )?;
+
+    let jpeg = fs::read(&output);
+    let _ = fs::remove_file(&output);
+    jpeg
+}
+
+/// Whether telegram will show the image as a thumbnail
+fn usable(jpeg: &[u8]) -> bool {
+    jpeg.len() <= MAX_THUMBNAIL_SIZE
+        && jpeg_dimensions(jpeg).is_some_and(|(width, height)| {
+            width <= MAX_THUMBNAIL_SIDE && height <= MAX_THUMBNAIL_SIDE
+        })
+}
+
+/// Reads the width and height from the start of frame segment
+fn jpeg_dimensions(jpeg: &[u8]) -> Option<(u16, u16)> {
+    let mut rest = jpeg.strip_prefix(&[0xFF, 0xD8])?;
+    loop {
+        let (&[0xFF, marker], segment) = rest.split_first_chunk::<2>()? else {
+            return None;
+        };
+        let length = u16::from_be_bytes(*segment.first_chunk::<2>()?) as usize;
+
+        match marker {
+            // SOF0-SOF15, except for DHT, JPG and DAC
+            0xC0..=0xCF if !matches!(marker, 0xC4 | 0xC8 | 0xCC) => {
+                let frame = segment.get(2..length)?;
+                let height = u16::from_be_bytes([*frame.get(1)?, *frame.get(2)?]);
+                let width = u16::from_be_bytes([*frame.get(3)?, *frame.get(4)?]);
+                return Some((width, height));
+            }
+            // the image data starts before a frame header was found
+            0xDA => return None,
+            _ => rest = segment.get(length..)?,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn jpeg(width: u16, height: u16) -> Vec<u8> {
+        let mut jpeg = vec![0xFF, 0xD8];
+        // an APP0 segment to skip over
+        jpeg.extend([0xFF, 0xE0, 0x00, 0x04, 0x00, 0x00]);
+        jpeg.extend([0xFF, 0xC0, 0x00, 0x0B, 0x08]);
+        jpeg.extend(height.to_be_bytes());
+        jpeg.extend(width.to_be_bytes());
+        jpeg.extend([0x01, 0x01, 0x11, 0x00]);
+        jpeg.extend([0xFF, 0xD9]);
+        jpeg
+    }
+
+    #[test]
+    fn check_thumbnail_dimensions() {
+        assert_eq!(jpeg_dimensions(&jpeg(320, 180)), Some((320, 180)));
+        assert!(usable(&jpeg(320, 180)));
+        assert!(!usable(&jpeg(1920, 1080)));
+
+        assert_eq!(jpeg_dimensions(b"\x89PNG\r\n"), None);
+        assert_eq!(jpeg_dimensions(&jpeg(320, 180)[..8]), None);
+    }
+}
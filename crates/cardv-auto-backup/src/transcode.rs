@@ -0,0 +1,106 @@
+//! Re-encoding of videos before they are uploaded, to save on slow connections
+
+use std::{fs, io, path::Path, time::Duration};
+
+use crate::{command, layout::Category};
+
+/// Long enough for a weak laptop to get through a 4K clip
+const TRANSCODE_TIMEOUT: Duration = Duration::from_secs(30 * 60);
+
+/// A suggestion for the encoder command, shown as a hint in the settings
+pub const EXAMPLE_COMMAND: &str = "ffmpeg -y -loglevel error -i {input} -c:v libx264 -preset veryfast -b:v {bitrate}k -maxrate {bitrate}k -bufsize {bitrate}k -vf scale=-2:min({height}\\,ih) -c:a aac -b:a 96k -movflags +faststart {output}";
+
+/// The target quality of the re-encoded videos
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum Preset {
+    Fhd,
+    Hd,
+    Sd,
+}
+
+impl Preset {
+    pub const ALL: [Self; 3] = [Self::Fhd, Self::Hd, Self::Sd];
+
+    /// In kbit/s
+    pub fn bitrate(&self) -> u32 {
+        match self {
+            Self::Fhd => 8000,
+            Self::Hd => 4000,
+            Self::Sd => 1500,
+        }
+    }
+
+    /// Videos are scaled down to this height, but never up
+    pub fn max_height(&self) -> u32 {
+        match self {
+            Self::Fhd => 1080,
+            Self::Hd => 720,
+            Self::Sd => 480,
+        }
+    }
+
+    pub fn label(&self) -> String {
+        format!(
+            "{}p at {:.1} Mbit/s",
+            self.max_height(),
+            self.bitrate() as f64 / 1000.0
+        )
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+pub struct Transcoding {
+    /// The encoder command, disabled if empty
+    ///
+    /// `{input}`, `{output}`, `{bitrate}` and `{height}` are replaced by
+    /// the video, the file to write, and the preset's values.
+    pub command: String,
+    pub preset: Preset,
+    /// Categories that are uploaded untouched, e.g. events that may serve as evidence
+    pub keep_original: Vec<Category>,
+}
+
+impl Default for Transcoding {
+    fn default() -> Self {
+        Self {
+            command: String::new(),
+            preset: Preset::Hd,
+            keep_original: vec![Category::Event],
+        }
+    }
+}
+
+impl Transcoding {
+    pub fn applies_to(&self, category: Category) -> bool {
+        !self.command.trim().is_empty()
+            && category != Category::Photo
+            && !self.keep_original.contains(&category)
+    }
+
+    /// Re-encodes the video into `out`, `Ok(false)` if that didn't make it any smaller
+    pub fn transcode(&self, path: &Path, out: &Path) -> io::Result<bool> {
+        let bitrate = self.preset.bitrate().to_string();
+        let height = self.preset.max_height().to_string();
+        command::run(
+            &self.command,
+            &[
+                ("input", path.as_os_str()),
+                ("output", out.as_os_str()),
+                ("bitrate", bitrate.as_ref()),
+                ("height", height.as_ref()),
+            ],
+            TRANSCODE_TIMEOUT,
+        )
+        .inspect_err(|_| {
+            let _ = fs::remove_file(out);
+        })?;
+
+        // already low bitrate footage (e.g. a rear camera) may grow
+        if fs::metadata(out)?.len() >= fs::metadata(path)?.len() {
+            fs::remove_file(out)?;
+            return Ok(false);
+        }
+        Ok(true)
+    }
+}
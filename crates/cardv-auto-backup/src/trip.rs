@@ -0,0 +1,323 @@
+//! Merging of the consecutive segments of a drive into a single video
+//!
+//! dashcams cut their recordings into 1-3 minute segments, which makes a single
+//! drive take dozens of messages. the segments follow each other with (almost)
+//! no gap, so they're found by their recording times, and their samples are
+//! copied one after the other into a new mp4, without re-encoding anything.
+//!
+//! only the tracks the mp4 crate can write make it into the merged video, so
+//! e.g. the GPS track of novatek cameras is dropped. h265 trips aren't merged
+//! at all (see [`mp4_file::ensure_copyable`]), their segments are sent one by one.
+
+use std::{
+    fs,
+    io::{Read, Seek},
+    path::{Path, PathBuf},
+};
+
+use mp4::{Mp4Reader, TrackConfig};
+
+use crate::{
+    clip::{Channel, TimestampSource},
+    layout::Category,
+    mp4_file::{self, impl_from_mp4_err, Mp4Err, Mp4Output},
+    usb::Recording,
+    video::VideoInfo,
+};
+
+/// Room left for the moov box of the merged video, which indexes all the samples
+const HEADROOM: u64 = 32 * 1024 * 1024;
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+pub struct TripMerging {
+    pub enabled: bool,
+    /// Segments further apart than this belong to different trips
+    pub max_gap_secs: u64,
+}
+
+impl Default for TripMerging {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            max_gap_secs: 30,
+        }
+    }
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum MergeErr {
+    #[error(transparent)]
+    File(#[from] Mp4Err),
+
+    #[error("the tracks of {0:?} don't match the first segment's")]
+    Incompatible(PathBuf),
+}
+
+impl_from_mp4_err!(MergeErr);
+
+/// Groups the segments of every trip into its first one, see [`Recording::trip`]
+///
+/// only the normal and parking recordings are merged, events are kept as they
+/// are. a trip is never bigger than `max_size`, so it can be uploaded in one piece.
+pub fn group(files: Vec<Recording>, merging: &TripMerging, max_size: u64) -> Vec<Recording> {
+    let max_gap = merging.max_gap_secs as f64;
+
+    let (mut segments, mut rest): (Vec<_>, Vec<_>) = files.into_iter().partition(|file| {
+        matches!(file.category, Category::Normal | Category::Parking)
+            && file.clip.source != TimestampSource::Modified
+    });
+    // the folder usually tells the category, and the lenses are recorded side by side
+    segments.sort_by_key(|file| {
+        (
+            file.path.parent().map(Path::to_path_buf),
+            file.clip.channel == Some(Channel::Rear),
+            file.clip.recorded_at,
+        )
+    });
+
+    // the trip being built, and the info of its last segment
+    let mut current: Option<(Recording, VideoInfo)> = None;
+    for segment in segments {
+        // broken segments can't be merged, and are uploaded on their own
+        let Ok(info) = VideoInfo::read(&segment.path) else {
+            rest.push(segment);
+            continue;
+        };
+
+        match &mut current {
+            Some((trip, last_info))
+                if continues(trip, last_info, &segment, &info, max_gap, max_size) =>
+            {
+                trip.trip.push(segment);
+                *last_info = info;
+            }
+            _ => {
+                if let Some((trip, _)) = current.replace((segment, info)) {
+                    rest.push(trip);
+                }
+            }
+        }
+    }
+    rest.extend(current.map(|(trip, _)| trip));
+
+    rest
+}
+
+/// Whether the segment was recorded right after the trip, by the same lens and settings
+fn continues(
+    trip: &Recording,
+    last_info: &VideoInfo,
+    segment: &Recording,
+    info: &VideoInfo,
+    max_gap: f64,
+    max_size: u64,
+) -> bool {
+    let last = trip.trip.last().unwrap_or(trip);
+    // the cameras round the names to the second, so the segments may seem to overlap
+    let gap = (segment.clip.recorded_at - last.clip.recorded_at).num_milliseconds() as f64 / 1000.0
+        - last_info.duration.as_secs_f64();
+
+    segment.category == trip.category
+        && segment.clip.channel == trip.clip.channel
+        && segment.path.parent() == trip.path.parent()
+        && gap.abs() <= max_gap
+        && (info.codec.as_str(), info.width, info.height, info.has_audio)
+            == (
+                last_info.codec.as_str(),
+                last_info.width,
+                last_info.height,
+                last_info.has_audio,
+            )
+        && trip.total_size() + segment.identity.size <= max_size.saturating_sub(HEADROOM)
+}
+
+/// Losslessly concatenates the segments of a trip into `out`
+///
+/// the tracks of the first segment are the ones written, and every other
+/// segment must have the same tracks, as it's the same camera recording.
+pub fn concat(paths: &[PathBuf], out: &Path) -> Result<(), MergeErr> {
+    let res = write_trip(paths, out);
+    if res.is_err() {
+        let _ = fs::remove_file(out);
+    }
+
+    res
+}
+
+fn write_trip(paths: &[PathBuf], out: &Path) -> Result<(), MergeErr> {
+    let mut segments = paths
+        .iter()
+        .map(|path| mp4_file::open(path))
+        .collect::<Result<Vec<_>, _>>()?;
+    let Some(first) = segments.first() else {
+        return Ok(());
+    };
+    for segment in &segments {
+        for track in segment.tracks().values() {
+            mp4_file::ensure_copyable(track)?;
+        }
+    }
+
+    let tracks = tracks(first);
+    let mut writer = Mp4Output::create(out, first, tracks.iter().map(|(_, config)| config))?;
+
+    // where the next segment starts, in the timescale of every track
+    let mut offsets = vec![0; tracks.len()];
+    for (segment, path) in segments.iter_mut().zip(paths) {
+        let segment_tracks = self::tracks(segment);
+        let compatible = segment_tracks.len() == tracks.len()
+            && segment_tracks
+                .iter()
+                .zip(&tracks)
+                .all(|((_, a), (_, b))| same_track(a, b));
+        if !compatible {
+            return Err(MergeErr::Incompatible(path.clone()));
+        }
+
+        for (idx, (id, _)) in segment_tracks.iter().enumerate() {
+            let mut end = 0;
+            for sample_id in 1..=segment.sample_count(*id)? {
+                let Some(mut sample) = segment.read_sample(*id, sample_id)? else {
+                    continue;
+                };
+                end = end.max(sample.start_time + u64::from(sample.duration));
+                sample.start_time += offsets[idx];
+                writer.write_sample(idx, &sample)?;
+            }
+            offsets[idx] += end;
+        }
+    }
+
+    writer.finish()?;
+    Ok(())
+}
+
+/// The tracks that can be written, by their id
+fn tracks<R: Read + Seek>(mp4: &Mp4Reader<R>) -> Vec<(u32, TrackConfig)> {
+    let mut ids = mp4.tracks().keys().copied().collect::<Vec<_>>();
+    ids.sort_unstable();
+    ids.into_iter()
+        .filter_map(|id| Some((id, mp4_file::track_config(&mp4.tracks()[&id]).ok()?)))
+        .collect()
+}
+
+fn same_track(a: &TrackConfig, b: &TrackConfig) -> bool {
+    a.track_type == b.track_type
+        && a.timescale == b.timescale
+        && std::mem::discriminant(&a.media_conf) == std::mem::discriminant(&b.media_conf)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::usb::fake;
+
+    fn segment(name: &str, second: i64, size: u64) -> Recording {
+        let mut segment =
+            fake::recording(PathBuf::from("Normal").join(name), Category::Normal, second);
+        segment.identity.size = size;
+        segment
+    }
+
+    fn info(seconds: u64) -> VideoInfo {
+        VideoInfo {
+            duration: Duration::from_secs(seconds),
+            width: 1920,
+            height: 1080,
+            rotation: 0,
+            codec: "h264".into(),
+            fps: 30.0,
+            bitrate: 8_000_000,
+            has_audio: true,
+        }
+    }
+
+    #[test]
+    fn continue_trips() {
+        const MAX_SIZE: u64 = 2000 * 1024 * 1024;
+        let first = segment("a.mp4", 0, 100);
+
+        // the next minute, named a second early
+        assert!(continues(
+            &first,
+            &info(60),
+            &segment("b.mp4", 59, 100),
+            &info(60),
+            30.0,
+            MAX_SIZE
+        ));
+        assert!(continues(
+            &first,
+            &info(60),
+            &segment("b.mp4", 80, 100),
+            &info(60),
+            30.0,
+            MAX_SIZE
+        ));
+        // a stop on the way
+        assert!(!continues(
+            &first,
+            &info(60),
+            &segment("b.mp4", 600, 100),
+            &info(60),
+            30.0,
+            MAX_SIZE
+        ));
+
+        let mut rear = segment("b.mp4", 60, 100);
+        rear.clip.channel = Some(Channel::Rear);
+        assert!(!continues(
+            &first,
+            &info(60),
+            &rear,
+            &info(60),
+            30.0,
+            MAX_SIZE
+        ));
+
+        let mut lower = info(60);
+        lower.height = 720;
+        assert!(!continues(
+            &first,
+            &info(60),
+            &segment("b.mp4", 60, 100),
+            &lower,
+            30.0,
+            MAX_SIZE
+        ));
+
+        // the gap is counted from the last segment of the trip
+        let mut trip = first.clone();
+        trip.trip.push(segment("b.mp4", 60, 100));
+        assert!(continues(
+            &trip,
+            &info(60),
+            &segment("c.mp4", 120, 100),
+            &info(60),
+            30.0,
+            MAX_SIZE
+        ));
+        assert!(!continues(
+            &trip,
+            &info(60),
+            &segment("c.mp4", 60, 100),
+            &info(60),
+            30.0,
+            MAX_SIZE
+        ));
+
+        // too big to be uploaded in one piece
+        let big = segment("b.mp4", 60, MAX_SIZE - HEADROOM);
+        assert!(!continues(
+            &first,
+            &info(60),
+            &big,
+            &info(60),
+            30.0,
+            MAX_SIZE
+        ));
+    }
+}
@@ -1,41 +1,142 @@
-use egui::TextBuffer;
-use std::{collections::HashSet, fs, io, path::PathBuf, time::Duration};
-use tokio::sync::mpsc as tokio_mpsc;
+use std::{
+    collections::{hash_map::RandomState, BTreeMap, HashSet},
+    fs,
+    future::Future,
+    io,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tokio::{
+    sync::{mpsc as tokio_mpsc, watch, Semaphore},
+    task::JoinSet,
+};
+use usb::{Volume, VolumeEvent, VolumeWatcher};
 
-use crate::tg::{Bot, BotErr};
+use crate::{
+    caption::{self, CaptionInfo},
+    clip::{ClipInfo, TimestampSource},
+    destination::{ConnectionEvent, Destination, DestinationErr, ErrorClass, MediaKind},
+    gps::{self, GpsPoint},
+    layout::{self, Category, LayoutProfile, SourceFolder},
+    ledger::{self, FileIdentity, Ledger, LedgerEntry, UploadStatus},
+    queue::UploadQueue,
+    repair::{self, RepairErr},
+    settings::Settings,
+    split, temp, thumbnail,
+    transcode::Transcoding,
+    trip,
+    video::VideoInfo,
+};
 
-/// Waits until an sd-card containing 'CARDV' is inserted into the computer
-///
-/// returns the path to the base folder containing all the records
+/// How many times a temporary failure is retried before giving up on the file
+const MAX_ATTEMPTS: u32 = 8;
+const BASE_BACKOFF: Duration = Duration::from_secs(5);
+const MAX_BACKOFF: Duration = Duration::from_secs(60 * 10);
+/// How long a request other than an upload (e.g. sending a message) may take
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(60 * 2);
+/// How long an upload may go without sending anything, however long it takes as a whole
+const UPLOAD_IDLE_TIMEOUT: Duration = Duration::from_secs(60 * 2);
+
+/// A source of inserted drives
 ///
-/// Notes:
-/// this function uses long-polling by querying the OS
-/// every interval lapse to see if a new device was plugged.
-pub fn wait_for_cardv_drive() -> PathBuf {
-    let mut checked: HashSet<char> = Default::default();
+/// this allows the uploader to be driven by
+/// something other than the OS (e.g. a fake in tests)
+pub trait DriveSource: Send + Sync + 'static {
+    /// Watch for volumes, starting with the ones that are already mounted
+    fn watch(&self) -> VolumeWatcher;
+}
 
-    loop {
-        // we need to re-initialize the checked set
-        // every time to not miss when a device is swapped.
-        let mut new_checked: HashSet<char> = Default::default();
-        for drive in usb::list_all_logical_drives() {
-            new_checked.insert(drive);
+/// The drives that are mounted by the OS
+#[derive(Debug, Clone, Copy, Default)]
+pub struct OsDriveSource;
 
-            if checked.contains(&drive) {
-                continue;
-            }
+impl DriveSource for OsDriveSource {
+    fn watch(&self) -> VolumeWatcher {
+        usb::watch_volumes()
+    }
+}
 
-            let path = PathBuf::from(format!("{}:\\CARDV\\Movie", drive));
-            if path.exists() {
-                return path;
-            }
+/// An inserted sd-card that contains dashcam recordings
+#[derive(Debug)]
+pub struct CardvDrive {
+    pub volume: Volume,
+    /// The name of the layout profile that matched the card
+    pub profile: String,
+    /// All the folders containing records
+    pub folders: Vec<SourceFolder>,
+}
+
+/// A file on the card that should be backed up
+#[derive(Debug, Clone)]
+pub struct Recording {
+    pub path: PathBuf,
+    pub category: Category,
+    pub clip: ClipInfo,
+    pub identity: FileIdentity,
+    /// The card's label, or the name of its layout profile
+    pub camera: String,
+    /// The segments recorded right after this one, which are uploaded with it as a single video
+    pub trip: Vec<Recording>,
+}
+
+impl Recording {
+    /// The size of the recording, along with the rest of its trip
+    pub fn total_size(&self) -> u64 {
+        self.identity.size
+            + self
+                .trip
+                .iter()
+                .map(|segment| segment.identity.size)
+                .sum::<u64>()
+    }
+
+    /// The files of the recording, starting with this one
+    pub fn paths(&self) -> impl Iterator<Item = &Path> {
+        std::iter::once(self)
+            .chain(&self.trip)
+            .map(|segment| segment.path.as_path())
+    }
+}
+
+/// Waits until an sd-card that matches one of the layout profiles is inserted
+///
+/// the volumes that are already mounted are checked first, returns
+/// None only if the OS can no longer be watched for new volumes.
+/// fixed disks are never picked, as generic folders like `Normal`
+/// or `Photo` would make any of them look like a dashcam.
+pub async fn wait_for_cardv_drive(
+    source: &impl DriveSource,
+    profiles: &[LayoutProfile],
+) -> Option<CardvDrive> {
+    let mut watcher = source.watch();
+
+    while let Some(event) = watcher.next().await {
+        let VolumeEvent::VolumeAdded(volume) = event else {
+            continue;
+        };
+        if !volume.removable {
+            tracing::debug!("skipping the fixed disk at {}", volume.mount_path.display());
+            continue;
         }
-        checked = new_checked;
 
-        // thanks to the hashing we can check it frequently without
-        // putting load on the system
-        std::thread::sleep(std::time::Duration::from_secs(1));
+        if let Some((profile, folders)) = layout::detect_layout(profiles, &volume.mount_path) {
+            tracing::info!(
+                label = ?volume.label,
+                serial = ?volume.serial,
+                profile = profile.name,
+                "found a cardv drive at {}",
+                volume.mount_path.display()
+            );
+            return Some(CardvDrive {
+                volume,
+                profile: profile.name.clone(),
+                folders,
+            });
+        }
     }
+
+    None
 }
 
 #[derive(Debug)]
@@ -46,65 +147,175 @@ pub struct DriveUploader {
 #[derive(Debug)]
 pub enum UploaderMsg {
     BadFileSystem,
-    Interrupted(BotErr),
-    Start(usize),
+    BadLedger(io::Error),
+    Interrupted(DestinationErr),
+    Connection(ConnectionEvent),
+    Start {
+        files: usize,
+        bytes: u64,
+    },
     Update(Update),
-    // we need a seperate update for when a file was
-    // uploaded successfully so we can save it to storage
-    Uploaded(PathBuf),
-    Done,
+    Progress(Progress),
+    /// The file has been sent (or given up on)
+    Completed(usize),
+    Done(Summary),
 }
 
 #[derive(Debug)]
 pub struct Update {
     pub uploading: String,
     pub current: usize,
+    pub size: u64,
+}
+
+/// Byte-level progress of one of the files that are being uploaded
+#[derive(Debug)]
+pub struct Progress {
+    pub current: usize,
+    pub sent: u64,
+}
+
+/// The outcome of a whole backup
+#[derive(Debug, Default)]
+pub struct Summary {
+    pub uploaded: usize,
+    pub duplicates: usize,
+    /// Broken clips that were uploaded after being repaired
+    pub repaired: usize,
+    /// Broken clips that were uploaded as they are
+    pub unrecoverable: usize,
+    pub failures: Vec<Failure>,
+}
+
+/// A file that was given up on
+#[derive(Debug)]
+pub struct Failure {
+    pub name: String,
+    pub reason: String,
 }
 
 impl DriveUploader {
-    pub fn new(bot: Bot, last_uploaded: Option<String>) -> Self {
+    /// Backs up the next inserted drive
+    ///
+    /// `last_upload` is the last file uploaded by versions that predate the
+    /// ledger, it and the files before it in its folder are recorded as uploaded.
+    pub fn new(
+        destination: impl Destination,
+        source: impl DriveSource,
+        settings: watch::Receiver<Settings>,
+        ledger_path: PathBuf,
+        last_upload: Option<PathBuf>,
+    ) -> Self {
         let (tx, rx) = tokio_mpsc::unbounded_channel();
         tokio::spawn(async move {
+            let profiles = settings.borrow().layout_profiles();
+            // stop waiting if the listener was dropped before a drive was inserted
+            let drive = tokio::select! {
+                drive = wait_for_cardv_drive(&source, &profiles) => drive,
+                _ = tx.closed() => return,
+            };
+            let Some(drive) = drive else {
+                let _ = tx.send(UploaderMsg::BadFileSystem);
+                return;
+            };
+            // the settings may have been edited while we waited for the drive
+            let settings = settings.borrow().clone();
+            let camera = drive
+                .volume
+                .label
+                .clone()
+                .unwrap_or_else(|| drive.profile.clone());
+
+            let mut ledger = match tokio::task::block_in_place(|| Ledger::open(&ledger_path)) {
+                Ok(ledger) => ledger,
+                Err(err) => {
+                    tracing::error!(?err, "failed to open the ledger");
+                    let _ = tx.send(UploaderMsg::BadLedger(err));
+                    return;
+                }
+            };
+
+            // the file must be on this very card, the mount path may be re-used by another one
+            let last_upload = last_upload
+                .filter(|path| path.starts_with(&drive.volume.mount_path) && path.is_file());
+
             let res = tokio::task::block_in_place(|| {
-                let base = wait_for_cardv_drive();
-                let mut files = fs::read_dir(base)?
-                    .map(|path| path.map(|path| path.path()))
-                    .filter(|path| match path {
-                        Ok(path) => path
-                            .to_string_lossy()
-                            .trim()
-                            .to_lowercase()
-                            .ends_with("mp4"),
-                        Err(_) => true,
-                    })
-                    .collect::<Result<Vec<_>, _>>()?;
-                files.sort(); // alpehetical ordering
-                let total = files.len();
-
-                // skip up to last-uploaded
-                if let Some(last_uploaded) = last_uploaded {
-                    // if 'last_uploaded' isn't part of the files we
-                    // should not skip anything because the dates may clamp
-                    if files
-                        .iter()
-                        .any(|file| file.to_string_lossy() == last_uploaded.as_str())
-                    {
-                        files.retain(|file| {
-                            file.to_string_lossy().as_str() > last_uploaded.as_str()
-                        });
+                let mut files = vec![];
+                let mut skip = 0;
+                for folder in drive.folders {
+                    for entry in fs::read_dir(folder.path)? {
+                        let entry = entry?;
+                        let path = entry.path();
+                        let name = path.to_string_lossy().trim().to_lowercase();
+                        if folder
+                            .category
+                            .extensions()
+                            .iter()
+                            .any(|extension| name.ends_with(extension))
+                        {
+                            let metadata = entry.metadata()?;
+                            let mut identity = FileIdentity::new(
+                                drive.volume.serial.clone(),
+                                &drive.volume.mount_path,
+                                &path,
+                                &metadata,
+                            )?;
+                            if ledger.is_uploaded(&identity) {
+                                skip += 1;
+                                continue;
+                            }
+
+                            let clip = ClipInfo::read(&path, &metadata)?;
+                            // the mtime can't tell clips apart once they're renamed or copied
+                            if clip.source != TimestampSource::Modified {
+                                identity.recorded_at = Some(clip.recorded_at);
+                                identity.channel = clip.channel;
+                            }
+
+                            // the old uploader sent the files of a folder by their name
+                            if last_upload
+                                .as_ref()
+                                .is_some_and(|last| path.parent() == last.parent() && path <= *last)
+                            {
+                                ledger.record(LedgerEntry::new(
+                                    identity,
+                                    UploadStatus::Uploaded,
+                                    None,
+                                ))?;
+                                skip += 1;
+                                continue;
+                            }
+
+                            files.push(Recording {
+                                path,
+                                category: folder.category,
+                                clip,
+                                identity,
+                                camera: camera.clone(),
+                                trip: vec![],
+                            });
+                        }
                     }
                 }
-                let skip = total - files.len();
 
-                Ok::<_, io::Error>((files, skip))
+                if settings.trips.enabled {
+                    let max_size = destination.max_file_size(&settings);
+                    files = trip::group(files, &settings.trips, max_size);
+                }
+                let mut queue = UploadQueue::new(settings.upload_order.clone());
+                queue.extend(files);
+
+                Ok::<_, io::Error>((queue, skip))
             });
 
-            let Ok((files, skip)) = res else {
+            let Ok((queue, skip)) = res else {
                 let _ = tx.send(UploaderMsg::BadFileSystem);
                 return;
             };
 
-            if let Err(err) = drive_upload_worker(bot, files, skip, tx.clone()).await {
+            if let Err(err) =
+                drive_upload_worker(destination, ledger, queue, skip, &settings, tx.clone()).await
+            {
                 tracing::error!("the upload has been failed: {err}");
                 let _ = tx.send(UploaderMsg::Interrupted(err));
             }
@@ -115,56 +326,1873 @@ impl DriveUploader {
 
     /// Pull a msg if there is any
     pub fn try_recv(&mut self) -> Option<UploaderMsg> {
-        match self.rx.try_recv() {
-            Ok(msg) => Some(msg),
-            Err(_) => None,
-        }
+        self.rx.try_recv().ok()
     }
 }
 
-#[tracing::instrument(skip(files))]
-async fn drive_upload_worker(
-    mut bot: Bot,
-    files: Vec<PathBuf>,
+/// The outcome of the concurrent part of a file's upload
+enum Prepared<M> {
+    /// The same content was already sent (possibly as another message), for every segment
+    Duplicate(Vec<(FileIdentity, Option<i32>)>),
+    Uploaded {
+        /// The segments of the trip that were already sent, and were left out of it
+        sent: Vec<(FileIdentity, Option<i32>)>,
+        /// Oversized videos are uploaded in several parts
+        media: Vec<M>,
+        /// Empty if the camera recorded no GPS data, or it's disabled
+        track: Vec<GpsPoint>,
+        recovery: Recovery,
+        /// The hash of the re-encoded copy that was uploaded instead
+        transcoded_hash: Option<String>,
+    },
+}
+
+/// A file whose upload went through its concurrent part, waiting for its turn to be sent
+type Ready<M> = (Recording, Result<Prepared<M>, DestinationErr>);
+
+/// The state a video was found in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Recovery {
+    Intact,
+    /// Its index was rebuilt, see [`repair`]
+    Repaired,
+    /// It's sent as a plain file
+    Unrecoverable,
+}
+
+#[tracing::instrument(skip(ledger, queue))]
+async fn drive_upload_worker<D: Destination>(
+    destination: D,
+    ledger: Ledger,
+    mut queue: UploadQueue,
     skip: usize,
+    settings: &Settings,
     tx: tokio_mpsc::UnboundedSender<UploaderMsg>,
-) -> Result<(), BotErr> {
-    let _ = tx.send(UploaderMsg::Start(files.len() + skip));
-
-    for (idx, file) in files.into_iter().enumerate() {
-        if tx
-            .send(UploaderMsg::Update(Update {
-                uploading: file.file_name().unwrap().to_string_lossy().to_string(),
-                current: idx + skip,
-            }))
-            .is_err()
-        {
+) -> Result<(), DestinationErr> {
+    let _ = tx.send(UploaderMsg::Start {
+        files: queue.len() + skip,
+        bytes: queue.size(),
+    });
+
+    let destination = Arc::new(destination);
+    let ledger = Arc::new(Mutex::new(ledger));
+    let concurrency = settings.concurrent_uploads.max(1);
+    let permits = Arc::new(Semaphore::new(concurrency));
+    // line up a few more files than are uploaded at once, so
+    // a slow file at the front doesn't stall all the others
+    let window = concurrency * 2;
+    let shared_settings = Arc::new(settings.clone());
+
+    let mut summary = Summary::default();
+    // the tasks are aborted if we return early
+    let mut uploads = JoinSet::new();
+    let mut ready = BTreeMap::new();
+    // the positions of the front clips that are sent along with the next one
+    let mut pairs = HashSet::new();
+    let mut queued = skip;
+    let mut next = skip;
+    loop {
+        while uploads.len() + ready.len() < window {
+            let Some(file) = queue.pop() else {
+                break;
+            };
+            // the clips of the two lenses are queued one after the other,
+            // and are later sent together as an album
+            let partner = queue
+                .peek()
+                .is_some_and(|other| is_pair(&file, other))
+                .then(|| queue.pop())
+                .flatten();
+            if partner.is_some() {
+                pairs.insert(queued);
+            }
+
+            for file in std::iter::once(file).chain(partner) {
+                uploads.spawn(prepare(
+                    destination.clone(),
+                    ledger.clone(),
+                    permits.clone(),
+                    file,
+                    queued,
+                    shared_settings.clone(),
+                    tx.clone(),
+                ));
+                queued += 1;
+            }
+        }
+
+        // the messages are sent in the order the files were queued
+        loop {
+            let count = if pairs.contains(&next) { 2 } else { 1 };
+            if !(next..next + count).all(|idx| ready.contains_key(&idx)) {
+                break;
+            }
+
+            if count == 2 {
+                let pair = [next, next + 1].map(|idx| ready.remove(&idx).unwrap());
+                finish_pair(
+                    &*destination,
+                    &ledger,
+                    &mut summary,
+                    pair,
+                    &settings.caption,
+                    &tx,
+                )
+                .await?;
+            } else {
+                let (file, prepared) = ready.remove(&next).unwrap();
+                finish(
+                    &*destination,
+                    &ledger,
+                    &mut summary,
+                    file,
+                    prepared,
+                    &settings.caption,
+                    &tx,
+                )
+                .await?;
+            }
+
+            for idx in next..next + count {
+                let _ = tx.send(UploaderMsg::Completed(idx));
+            }
+            next += count;
+        }
+
+        if tx.is_closed() {
             tracing::info!("early termination of upload worker because listener was dropped");
             return Ok(());
         }
 
-        // retry every increasing interval until successfull upload
-        let mut interval = 60 * 10;
-        loop {
-            // Wait a bit to avoid hiting rate limits
-            tokio::time::sleep(Duration::from_secs(30)).await;
-            // re-login to reset connection issues
-            bot = Bot::from_packed(bot.packed()).await?;
+        match uploads.join_next().await {
+            Some(res) => {
+                let (current, file, prepared) = res.expect("upload tasks should never panic");
+                ready.insert(current, (file, prepared));
+            }
+            None => break,
+        }
+    }
+
+    let _ = tx.send(UploaderMsg::Done(summary));
 
-            if let Ok(res) =
-                tokio::time::timeout(Duration::from_secs(interval), bot.upload_mp4(file.clone()))
+    Ok(())
+}
+
+/// Whether the recordings are the two lenses of a camera at the same moment
+fn is_pair(first: &Recording, second: &Recording) -> bool {
+    first.category == second.category
+        && first.category != Category::Photo
+        && first.clip.pairs_with(&second.clip)
+}
+
+/// Hashes and uploads the file, once there is a free slot
+async fn prepare<D: Destination>(
+    destination: Arc<D>,
+    ledger: Arc<Mutex<Ledger>>,
+    permits: Arc<Semaphore>,
+    mut file: Recording,
+    current: usize,
+    settings: Arc<Settings>,
+    tx: tokio_mpsc::UnboundedSender<UploaderMsg>,
+) -> (usize, Recording, Result<Prepared<D::Media>, DestinationErr>) {
+    let _permit = permits.acquire_owned().await;
+    let _ = tx.send(UploaderMsg::Update(Update {
+        uploading: file.path.file_name().unwrap().to_string_lossy().to_string(),
+        current,
+        size: file.total_size(),
+    }));
+
+    // every segment of a trip is checked on its own, as only some of them may have been sent
+    let mut sent = vec![];
+    let mut unsent = vec![];
+    let head = Recording {
+        trip: vec![],
+        ..file.clone()
+    };
+    for mut segment in std::iter::once(head).chain(file.trip.clone()) {
+        match find_sent(&*destination, &ledger, &mut segment, &tx).await {
+            Ok(Some(message_id)) => sent.push((segment.identity, message_id)),
+            Ok(None) => unsent.push(segment),
+            Err(err) => return (current, file, Err(err)),
+        }
+    }
+    let mut unsent = unsent.into_iter();
+    let Some(head) = unsent.next() else {
+        return (current, file, Ok(Prepared::Duplicate(sent)));
+    };
+    file = Recording {
+        trip: unsent.collect(),
+        ..head
+    };
+
+    // the segments of a trip are sent as a single video
+    let mut merged = None;
+    if !file.trip.is_empty() {
+        merged = merge(&file).await;
+    }
+    let unmerged = !file.trip.is_empty() && merged.is_none();
+
+    // the clip that was recording when the power was cut is often left unreadable
+    let (recovery, mut source) = match merged {
+        Some(merged) => (Recovery::Intact, merged),
+        // the segments of a trip could all be read when it was put together
+        None if unmerged || file.category == Category::Photo => {
+            (Recovery::Intact, file.path.clone())
+        }
+        None => match recover(&file).await {
+            Ok(None) => (Recovery::Intact, file.path.clone()),
+            Ok(Some(repaired)) => (Recovery::Repaired, repaired),
+            Err(err) => {
+                tracing::warn!(path = ?file.path, "the clip is broken beyond repair: {err}");
+                (Recovery::Unrecoverable, file.path.clone())
+            }
+        },
+    };
+    // files of our own, to be removed once uploaded
+    let mut temporary = vec![];
+    if source != file.path {
+        temporary.push(source.clone());
+    }
+
+    // re-encoding makes the upload quicker, unless the original is to be kept
+    let mut transcoded_hash = None;
+    if recovery != Recovery::Unrecoverable
+        && !unmerged
+        && settings.transcoding.applies_to(file.category)
+    {
+        if let Some((transcoded, hash)) = transcode(&file, &source, &settings.transcoding).await {
+            temporary.push(transcoded.clone());
+            source = transcoded;
+            transcoded_hash = Some(hash);
+        }
+    }
+
+    let size = if source == file.path {
+        file.total_size()
+    } else {
+        tokio::fs::metadata(&source)
+            .await
+            .map_or(0, |meta| meta.len())
+    };
+    if size != file.total_size() {
+        let _ = tx.send(UploaderMsg::Update(Update {
+            uploading: file.path.file_name().unwrap().to_string_lossy().to_string(),
+            current,
+            size,
+        }));
+    }
+
+    let on_progress = |sent| {
+        let _ = tx.send(UploaderMsg::Progress(Progress { current, sent }));
+    };
+    let thumbnail = match (file.category, recovery) {
+        (Category::Photo, _) | (_, Recovery::Unrecoverable) => None,
+        _ => thumbnail::thumbnail(source.clone(), settings.thumbnail_command()).await,
+    };
+    let max_size = destination.max_file_size(&settings);
+    let res = {
+        let (destination, file, source, thumbnail) =
+            (&*destination, &file, &source, thumbnail.as_deref());
+        match (file.category, recovery) {
+            (_, Recovery::Unrecoverable) => uploading(
+                destination,
+                file,
+                &tx,
+                source,
+                MediaKind::Document,
+                &on_progress,
+            )
+            .await
+            .map(|media| vec![media]),
+            (Category::Photo, _) => uploading(
+                destination,
+                file,
+                &tx,
+                source,
+                MediaKind::Photo,
+                &on_progress,
+            )
+            .await
+            .map(|media| vec![media]),
+            _ if unmerged => {
+                let segments = file.paths().map(Path::to_path_buf).collect::<Vec<_>>();
+                upload_series(destination, file, &segments, thumbnail, current, &tx).await
+            }
+            _ if size > max_size => {
+                upload_parts(destination, file, source, max_size, thumbnail, current, &tx).await
+            }
+            _ => {
+                let kind = MediaKind::Video { thumbnail };
+                uploading(destination, file, &tx, source, kind, &on_progress)
                     .await
-            {
-                res?;
-                break;
+                    .map(|media| vec![media])
             }
+        }
+    };
+    for path in temporary {
+        let _ = tokio::fs::remove_file(path).await;
+    }
+
+    let track = match file.category {
+        Category::Photo => vec![],
+        _ if !settings.gps_tracks => vec![],
+        _ => {
+            let paths = file.paths().map(Path::to_path_buf).collect::<Vec<_>>();
+            blocking(move || {
+                let mut track = vec![];
+                for path in paths {
+                    track.extend(gps::extract(&path)?);
+                }
+                Ok::<_, io::Error>(track)
+            })
+            .await
+            .unwrap_or_else(|err| {
+                tracing::warn!(path = ?file.path, "failed to read the GPS track: {err}");
+                vec![]
+            })
+        }
+    };
+
+    (
+        current,
+        file,
+        res.map(|media| Prepared::Uploaded {
+            sent,
+            media,
+            track,
+            recovery,
+            transcoded_hash,
+        }),
+    )
+}
+
+/// The message a copy of the clip was sent in, `None` if it has to be uploaded
+///
+/// the clip is hashed unless the ledger already knows it by its name and card.
+async fn find_sent<D: Destination>(
+    destination: &D,
+    ledger: &Mutex<Ledger>,
+    file: &mut Recording,
+    tx: &tokio_mpsc::UnboundedSender<UploaderMsg>,
+) -> Result<Option<Option<i32>>, DestinationErr> {
+    // the camera may have renamed or moved (e.g. locked) a clip that was already uploaded
+    let original = ledger
+        .lock()
+        .unwrap()
+        .find_by_clip(&file.identity)
+        .map(|original| (original.file.relative_path.clone(), original.message_id));
+    if let Some((original, message_id)) = original {
+        if still_sent(destination, file, tx, message_id).await? {
+            tracing::info!(path = ?file.path, "skipping a moved copy of {original}");
+            return Ok(Some(message_id));
+        }
+    }
+
+    // the same clip may have been uploaded from another card or under another name
+    let hash = ledger::content_hash(file.path.clone()).await?;
+    let original = ledger
+        .lock()
+        .unwrap()
+        .find_by_hash(&hash)
+        .map(|original| (original.file.relative_path.clone(), original.message_id));
+    file.identity.hash = Some(hash);
+    if let Some((original, message_id)) = original {
+        if still_sent(destination, file, tx, message_id).await? {
+            tracing::info!(path = ?file.path, "skipping a duplicate of {original}");
+            return Ok(Some(message_id));
+        }
+        tracing::info!(path = ?file.path, "{original} was deleted, uploading it again");
+    }
+
+    Ok(None)
+}
+
+/// Whether the original of a duplicate is still there, as it may have been deleted by hand
+///
+/// originals that were recorded without an id can't be checked, and are assumed to be.
+async fn still_sent<D: Destination>(
+    destination: &D,
+    file: &Recording,
+    tx: &tokio_mpsc::UnboundedSender<UploaderMsg>,
+    message_id: Option<i32>,
+) -> Result<bool, DestinationErr> {
+    match message_id {
+        Some(id) => retrying(destination, file, tx, || destination.exists(id)).await,
+        None => Ok(true),
+    }
+}
+
+/// Concatenates the segments of the trip, None if they have to be sent one by one
+async fn merge(file: &Recording) -> Option<PathBuf> {
+    let out = temp::unique_path(&file.path, "trip.mp4");
+    let paths = file.paths().map(Path::to_path_buf).collect::<Vec<_>>();
+
+    let res = blocking({
+        let out = out.clone();
+        move || {
+            fs::create_dir_all(out.parent().unwrap())?;
+            trip::concat(&paths, &out)
+        }
+    })
+    .await;
+
+    match res {
+        Ok(()) => {
+            tracing::info!(path = ?file.path, "merged {} segments", file.trip.len() + 1);
+            Some(out)
+        }
+        Err(err) => {
+            tracing::warn!(path = ?file.path, "failed to merge the trip ({err}), sending its segments separately");
+            None
+        }
+    }
+}
+
+/// Re-encodes the video, returning the smaller copy and its hash
+///
+/// the original is uploaded if anything goes wrong.
+async fn transcode(
+    file: &Recording,
+    source: &Path,
+    transcoding: &Transcoding,
+) -> Option<(PathBuf, String)> {
+    let out = temp::unique_path(&file.path, "transcoded.mp4");
+
+    let res = blocking({
+        let (source, out, transcoding) = (source.to_path_buf(), out.clone(), transcoding.clone());
+        move || {
+            fs::create_dir_all(out.parent().unwrap())?;
+            transcoding.transcode(&source, &out)
+        }
+    })
+    .await;
+
+    match res {
+        Ok(true) => {}
+        Ok(false) => {
+            tracing::info!(path = ?file.path, "transcoding wouldn't make the clip any smaller");
+            return None;
+        }
+        Err(err) => {
+            tracing::warn!(path = ?file.path, "failed to transcode the clip: {err}");
+            return None;
+        }
+    }
+
+    match ledger::content_hash(out.clone()).await {
+        Ok(hash) => Some((out, hash)),
+        Err(err) => {
+            tracing::warn!(path = ?file.path, "failed to hash the transcoded clip: {err}");
+            let _ = tokio::fs::remove_file(&out).await;
+            None
+        }
+    }
+}
+
+/// Repairs the clip if it can't be read, returning the repaired copy
+///
+/// `Ok(None)` if the clip is fine as it is.
+async fn recover(file: &Recording) -> Result<Option<PathBuf>, RepairErr> {
+    let path = file.path.clone();
+    let out = temp::unique_path(&file.path, "repaired.mp4");
+
+    blocking(move || {
+        if !repair::is_broken(&path)? {
+            return Ok(None);
+        }
+
+        let reference = repair::find_reference(&path).ok_or(RepairErr::NoReference)?;
+        fs::create_dir_all(out.parent().unwrap())?;
+        let frames = repair::repair(&path, &reference, &out)?;
+        tracing::info!(?path, ?reference, "recovered {frames} frames");
+
+        Ok(Some(out))
+    })
+    .await
+}
+
+/// Splits a video that is too big for telegram, and uploads its parts one after another
+async fn upload_parts<D: Destination>(
+    destination: &D,
+    file: &Recording,
+    path: &Path,
+    max_size: u64,
+    thumbnail: Option<&[u8]>,
+    current: usize,
+    tx: &tokio_mpsc::UnboundedSender<UploaderMsg>,
+) -> Result<Vec<D::Media>, DestinationErr> {
+    let dir = temp::unique_path(&file.path, "parts");
+    tokio::fs::create_dir_all(&dir).await?;
+
+    let res = async {
+        let parts = blocking({
+            let (path, dir) = (path.to_path_buf(), dir.clone());
+            move || split::split_mp4(&path, max_size, &dir)
+        })
+        .await?;
+        tracing::info!(path = ?file.path, "split into {} parts", parts.len());
+
+        upload_series(destination, file, &parts, thumbnail, current, tx).await
+    }
+    .await;
 
-            interval += 60;
+    let _ = tokio::fs::remove_dir_all(&dir).await;
+    res
+}
+
+/// Uploads the videos one after another, e.g. the parts of a split video
+async fn upload_series<D: Destination>(
+    destination: &D,
+    file: &Recording,
+    paths: &[PathBuf],
+    thumbnail: Option<&[u8]>,
+    current: usize,
+    tx: &tokio_mpsc::UnboundedSender<UploaderMsg>,
+) -> Result<Vec<D::Media>, DestinationErr> {
+    let mut uploaded = vec![];
+    // the progress covers all the videos, as if they were a single file
+    let mut offset = 0;
+    for path in paths {
+        let on_progress = |sent| {
+            let _ = tx.send(UploaderMsg::Progress(Progress {
+                current,
+                sent: offset + sent,
+            }));
+        };
+        let kind = MediaKind::Video { thumbnail };
+        let media = uploading(destination, file, tx, path, kind, &on_progress).await?;
+
+        offset += tokio::fs::metadata(path).await?.len();
+        uploaded.push(media);
+    }
+
+    Ok(uploaded)
+}
+
+/// Sends the uploaded file to the channel, and records the outcome
+async fn finish<D: Destination>(
+    destination: &D,
+    ledger: &Mutex<Ledger>,
+    summary: &mut Summary,
+    file: Recording,
+    prepared: Result<Prepared<D::Media>, DestinationErr>,
+    template: &str,
+    tx: &tokio_mpsc::UnboundedSender<UploaderMsg>,
+) -> Result<(), DestinationErr> {
+    let (media, track, recovery, transcoded_hash) = match prepared {
+        Ok(Prepared::Uploaded {
+            sent,
+            media,
+            track,
+            recovery,
+            transcoded_hash,
+        }) => {
+            record_duplicate(&mut ledger.lock().unwrap(), summary, sent)?;
+            (media, track, recovery, transcoded_hash)
+        }
+        Ok(Prepared::Duplicate(sent)) => {
+            return record_duplicate(&mut ledger.lock().unwrap(), summary, sent)
+        }
+        Err(err) => return give_up(&mut ledger.lock().unwrap(), summary, file, err),
+    };
+
+    // the original the ledger knows of may be the one that was found deleted
+    if let Some(message_id) = sent_copy(ledger, &file) {
+        match still_sent(destination, &file, tx, message_id).await {
+            Ok(true) => {
+                let sent = vec![(file.identity, message_id)];
+                return record_duplicate(&mut ledger.lock().unwrap(), summary, sent);
+            }
+            Ok(false) => {}
+            Err(err) => return give_up(&mut ledger.lock().unwrap(), summary, file, err),
+        }
+    }
+
+    // the parts of a split video reply to the first one, so they show up as a series
+    let mut sent = vec![];
+    let res = async {
+        let mut first = None;
+        for (idx, part) in media.iter().enumerate() {
+            let part_of = (media.len() > 1).then_some((idx + 1, media.len()));
+            let info = D::video_info(part);
+            let caption = &render_caption(destination, template, &file, info, recovery, part_of);
+            let message_id = retrying(destination, &file, tx, || {
+                destination.send(part, caption.clone(), first)
+            })
+            .await?;
+            first.get_or_insert(message_id);
+            sent.push(message_id);
         }
-        let _ = tx.send(UploaderMsg::Uploaded(file));
+
+        Ok::<_, DestinationErr>(first.expect("there is always at least one part"))
     }
+    .await;
 
-    let _ = tx.send(UploaderMsg::Done);
+    let message_id = match res {
+        Ok(message_id) => message_id,
+        Err(err) => {
+            // the whole series is sent again on the next run, so the parts that made it are removed
+            if !sent.is_empty() && err.class() != ErrorClass::Fatal {
+                if let Err(err) =
+                    retrying(destination, &file, tx, || destination.delete(&sent)).await
+                {
+                    tracing::warn!(path = ?file.path, "failed to delete the parts that were sent: {err}");
+                }
+            }
+            return give_up(&mut ledger.lock().unwrap(), summary, file, err);
+        }
+    };
+    record_uploaded(
+        &mut ledger.lock().unwrap(),
+        summary,
+        &file,
+        Some(message_id),
+        transcoded_hash,
+    )?;
+    match recovery {
+        Recovery::Intact => {}
+        Recovery::Repaired => summary.repaired += 1,
+        Recovery::Unrecoverable => summary.unrecoverable += 1,
+    }
 
+    send_track(destination, &file, &track, message_id, tx).await;
     Ok(())
 }
+
+/// Replies to the video with its GPS track, and a pin where it starts
+///
+/// the video itself is already sent, so failing here only loses the track.
+async fn send_track<D: Destination>(
+    destination: &D,
+    file: &Recording,
+    track: &[GpsPoint],
+    message_id: i32,
+    tx: &tokio_mpsc::UnboundedSender<UploaderMsg>,
+) {
+    let Some(start) = track.first() else {
+        return;
+    };
+    let name = file
+        .path
+        .file_stem()
+        .unwrap_or_default()
+        .to_string_lossy()
+        .to_string();
+    let gpx = gps::to_gpx(&name, track);
+
+    let res = async {
+        retrying(destination, file, tx, || {
+            destination.send_document(
+                format!("{name}.gpx"),
+                gps::GPX_MIME_TYPE,
+                gpx.as_bytes(),
+                Some(message_id),
+            )
+        })
+        .await?;
+        retrying(destination, file, tx, || {
+            destination.send_location(start.latitude, start.longitude, Some(message_id))
+        })
+        .await
+    }
+    .await;
+
+    if let Err(err) = res {
+        tracing::warn!(path = ?file.path, "failed to send the GPS track: {err}");
+    }
+}
+
+/// Sends a front/rear pair as a single album, falling back to separate messages
+async fn finish_pair<D: Destination>(
+    destination: &D,
+    ledger: &Mutex<Ledger>,
+    summary: &mut Summary,
+    pair: [Ready<D::Media>; 2],
+    template: &str,
+    tx: &tokio_mpsc::UnboundedSender<UploaderMsg>,
+) -> Result<(), DestinationErr> {
+    let [(first, first_prepared), (second, second_prepared)] = pair;
+
+    // split videos, broken clips and duplicates can't be part of an album
+    let album = match (&first_prepared, &second_prepared) {
+        (
+            Ok(Prepared::Uploaded {
+                sent: first_sent,
+                media: first_media,
+                track: first_track,
+                recovery: Recovery::Intact,
+                transcoded_hash: first_hash,
+            }),
+            Ok(Prepared::Uploaded {
+                sent: second_sent,
+                media: second_media,
+                track: second_track,
+                recovery: Recovery::Intact,
+                transcoded_hash: second_hash,
+            }),
+        ) if first_media.len() == 1
+            && second_media.len() == 1
+            && sent_copy(ledger, &first).is_none()
+            && sent_copy(ledger, &second).is_none() =>
+        {
+            Some((
+                [&first_media[0], &second_media[0]],
+                [first_track, second_track],
+                [first_hash, second_hash],
+                [first_sent, second_sent],
+            ))
+        }
+        _ => None,
+    };
+
+    if let Some((album, tracks, transcoded_hashes, sent)) = album {
+        let info = D::video_info(album[0]);
+        let caption = render_caption(destination, template, &first, info, Recovery::Intact, None);
+        let res = retrying(destination, &first, tx, || {
+            destination.send_album(&album, caption.clone())
+        })
+        .await;
+
+        match res {
+            Ok(message_ids) => {
+                for sent in sent {
+                    record_duplicate(&mut ledger.lock().unwrap(), summary, sent.clone())?;
+                }
+                let files = [&first, &second];
+                let message_ids = [0, 1].map(|idx| message_ids.get(idx).copied().flatten());
+                for ((file, message_id), transcoded_hash) in
+                    files.iter().zip(message_ids).zip(transcoded_hashes)
+                {
+                    record_uploaded(
+                        &mut ledger.lock().unwrap(),
+                        summary,
+                        file,
+                        message_id,
+                        transcoded_hash.clone(),
+                    )?;
+                }
+
+                // usually only one of the lenses has the receiver
+                for ((file, track), message_id) in files.iter().zip(tracks).zip(message_ids) {
+                    if let Some(message_id) = message_id {
+                        send_track(destination, file, track, message_id, tx).await;
+                    }
+                }
+                return Ok(());
+            }
+            Err(err) if err.class() == ErrorClass::Fatal => return Err(err),
+            Err(err) => {
+                tracing::warn!(path = ?first.path, "failed to send the album ({err}), sending the clips separately");
+            }
+        }
+    }
+
+    finish(
+        destination,
+        ledger,
+        summary,
+        first,
+        first_prepared,
+        template,
+        tx,
+    )
+    .await?;
+    finish(
+        destination,
+        ledger,
+        summary,
+        second,
+        second_prepared,
+        template,
+        tx,
+    )
+    .await
+}
+
+/// The message id of a copy of the file that was sent while it was uploading
+///
+/// trips are left out, as only some of their segments may have such a copy.
+fn sent_copy(ledger: &Mutex<Ledger>, file: &Recording) -> Option<Option<i32>> {
+    if !file.trip.is_empty() {
+        return None;
+    }
+    let hash = file.identity.hash.as_deref()?;
+    let ledger = ledger.lock().unwrap();
+    ledger
+        .find_by_hash(hash)
+        .map(|original| original.message_id)
+}
+
+/// The caption of the file, or of its `(part, count)` part, cut short to fit the destination
+fn render_caption(
+    destination: &impl Destination,
+    template: &str,
+    file: &Recording,
+    info: Option<&VideoInfo>,
+    recovery: Recovery,
+    part_of: Option<(usize, usize)>,
+) -> String {
+    let caption = caption::render(
+        template,
+        &CaptionInfo {
+            recorded_at: file.clip.recorded_at,
+            camera: file.camera.clone(),
+            category: file.category,
+            duration: info.map(|info| info.duration),
+            resolution: info.map(|info| (info.width.into(), info.height.into())),
+            size: file.total_size(),
+        },
+    );
+
+    // tagged so that they can be looked up in the channel
+    let mut tags = vec![];
+    match recovery {
+        Recovery::Intact => {}
+        Recovery::Repaired => tags.push("#repaired".to_string()),
+        Recovery::Unrecoverable => tags.push("#unrecoverable".to_string()),
+    }
+    if !file.trip.is_empty() {
+        tags.push(format!("#trip of {} clips", file.trip.len() + 1));
+    }
+    if let Some((part, count)) = part_of {
+        tags.push(format!("part {part}/{count}"));
+    }
+
+    caption::with_tags(&caption, &tags, destination.max_caption_len())
+}
+
+/// Records the file, and the rest of its trip, which was sent in the same message
+fn record_uploaded(
+    ledger: &mut Ledger,
+    summary: &mut Summary,
+    file: &Recording,
+    message_id: Option<i32>,
+    transcoded_hash: Option<String>,
+) -> Result<(), DestinationErr> {
+    ledger.record(LedgerEntry {
+        transcoded_hash,
+        ..LedgerEntry::new(file.identity.clone(), UploadStatus::Uploaded, message_id)
+    })?;
+    for segment in &file.trip {
+        ledger.record(LedgerEntry::new(
+            segment.identity.clone(),
+            UploadStatus::Uploaded,
+            message_id,
+        ))?;
+    }
+    summary.uploaded += 1 + file.trip.len();
+
+    Ok(())
+}
+
+/// Records the files as copies of what was already sent, by the message they were sent in
+fn record_duplicate(
+    ledger: &mut Ledger,
+    summary: &mut Summary,
+    sent: Vec<(FileIdentity, Option<i32>)>,
+) -> Result<(), DestinationErr> {
+    for (identity, message_id) in sent {
+        ledger.record(LedgerEntry::new(
+            identity,
+            UploadStatus::Duplicate,
+            message_id,
+        ))?;
+        summary.duplicates += 1;
+    }
+
+    Ok(())
+}
+
+/// Records a file that couldn't be uploaded, or bails if the error is fatal
+fn give_up(
+    ledger: &mut Ledger,
+    summary: &mut Summary,
+    file: Recording,
+    err: DestinationErr,
+) -> Result<(), DestinationErr> {
+    let status = match err.class() {
+        ErrorClass::Fatal => return Err(err),
+        ErrorClass::Skippable => UploadStatus::Skipped,
+        // we ran out of attempts
+        ErrorClass::Retryable => UploadStatus::Failed,
+    };
+    skip_file(ledger, summary, file, status, err)
+}
+
+/// Runs the request, retrying temporary failures with an exponential backoff
+async fn retrying<D: Destination, T, F>(
+    destination: &D,
+    file: &Recording,
+    tx: &tokio_mpsc::UnboundedSender<UploaderMsg>,
+    request: impl Fn() -> F,
+) -> Result<T, DestinationErr>
+where
+    F: Future<Output = Result<T, DestinationErr>>,
+{
+    with_retries(destination, file, tx, || async {
+        tokio::time::timeout(REQUEST_TIMEOUT, request())
+            .await
+            .map_err(|_| DestinationErr::Timeout)?
+    })
+    .await
+}
+
+/// Uploads the file like [`retrying`], but only times out an upload that stopped sending
+///
+/// a big file on a slow link takes far longer than any fixed limit would allow.
+async fn uploading<D: Destination>(
+    destination: &D,
+    file: &Recording,
+    tx: &tokio_mpsc::UnboundedSender<UploaderMsg>,
+    path: &Path,
+    kind: MediaKind<'_>,
+    on_progress: &(impl Fn(u64) + Send + Sync),
+) -> Result<D::Media, DestinationErr> {
+    with_retries(destination, file, tx, || async {
+        let (activity_tx, mut activity) = watch::channel(());
+        let upload = destination.upload(path.to_path_buf(), kind, move |sent| {
+            activity_tx.send_replace(());
+            on_progress(sent);
+        });
+        tokio::pin!(upload);
+
+        loop {
+            tokio::select! {
+                res = &mut upload => return res,
+                changed = tokio::time::timeout(UPLOAD_IDLE_TIMEOUT, activity.changed()) => {
+                    match changed {
+                        Ok(Ok(())) => {}
+                        // the upload is done reporting its progress, and only has to finish
+                        Ok(Err(_)) => break,
+                        Err(_) => return Err(DestinationErr::Timeout),
+                    }
+                }
+            }
+        }
+        tokio::time::timeout(UPLOAD_IDLE_TIMEOUT, upload)
+            .await
+            .map_err(|_| DestinationErr::Timeout)?
+    })
+    .await
+}
+
+/// Runs the request until it succeeds, or fails in a way that retrying won't fix
+async fn with_retries<D: Destination, T, F>(
+    destination: &D,
+    file: &Recording,
+    tx: &tokio_mpsc::UnboundedSender<UploaderMsg>,
+    request: impl Fn() -> F,
+) -> Result<T, DestinationErr>
+where
+    F: Future<Output = Result<T, DestinationErr>>,
+{
+    let mut attempt = 0;
+    loop {
+        let res = async {
+            destination
+                .ensure_connected(|event| {
+                    let _ = tx.send(UploaderMsg::Connection(event));
+                })
+                .await?;
+
+            request().await
+        }
+        .await;
+
+        match res {
+            Ok(res) => return Ok(res),
+            Err(err) if err.class() == ErrorClass::Retryable && attempt < MAX_ATTEMPTS => {
+                if let DestinationErr::Timeout = err {
+                    // the connection may have silently died
+                    destination.connection_failed();
+                }
+
+                let backoff = backoff(attempt);
+                attempt += 1;
+                tracing::warn!(path = ?file.path, "attempt {attempt} failed ({err}), retrying in {backoff:?}");
+                tokio::time::sleep(backoff).await;
+            }
+            Err(err) => return Err(err),
+        }
+    }
+}
+
+/// Exponential backoff with 'equal jitter', so retries don't line up
+fn backoff(attempt: u32) -> Duration {
+    use std::hash::{BuildHasher, Hasher};
+
+    let delay = BASE_BACKOFF
+        .saturating_mul(2u32.saturating_pow(attempt))
+        .min(MAX_BACKOFF);
+
+    // every RandomState is randomly seeded, which is good enough for jitter
+    let random = RandomState::new().build_hasher().finish();
+    let fraction = random as f64 / u64::MAX as f64;
+
+    delay / 2 + (delay / 2).mul_f64(fraction)
+}
+
+/// Runs file work on the blocking thread pool, a panic in it fails as an io error
+async fn blocking<T, E>(work: impl FnOnce() -> Result<T, E> + Send + 'static) -> Result<T, E>
+where
+    T: Send + 'static,
+    E: From<io::Error> + Send + 'static,
+{
+    tokio::task::spawn_blocking(work)
+        .await
+        .map_err(io::Error::other)?
+}
+
+fn skip_file(
+    ledger: &mut Ledger,
+    summary: &mut Summary,
+    file: Recording,
+    status: UploadStatus,
+    err: DestinationErr,
+) -> Result<(), DestinationErr> {
+    tracing::error!(path = ?file.path, "giving up on the file: {err}");
+    summary.failures.push(Failure {
+        name: file.path.file_name().unwrap().to_string_lossy().to_string(),
+        reason: err.to_string(),
+    });
+    ledger.record(LedgerEntry {
+        error: Some(err.to_string()),
+        ..LedgerEntry::new(file.identity, status, None)
+    })?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+pub mod fake {
+    use std::{
+        collections::{HashMap, HashSet},
+        fs,
+        path::PathBuf,
+        sync::{Arc, Mutex},
+        time::Duration,
+    };
+
+    use chrono::NaiveDate;
+    use tokio::sync::mpsc as tokio_mpsc;
+    use usb::{FileSystem, Volume, VolumeEvent, VolumeWatcher};
+
+    use super::{DriveSource, Recording};
+    use crate::{
+        clip::{ClipInfo, TimestampSource},
+        destination::{ConnectionEvent, Destination, DestinationErr, MediaKind},
+        layout::Category,
+        ledger::FileIdentity,
+        settings::Settings,
+        video::VideoInfo,
+    };
+
+    /// A drive source backed by a temp directory
+    ///
+    /// every card is a sub-directory named after its serial, which
+    /// can be populated before it's inserted.
+    #[derive(Debug, Clone)]
+    pub struct FakeDriveSource {
+        root: Arc<tempfile::TempDir>,
+        inner: Arc<Mutex<Inner>>,
+    }
+
+    #[derive(Debug, Default)]
+    struct Inner {
+        inserted: HashMap<String, Volume>,
+        watchers: Vec<tokio_mpsc::UnboundedSender<VolumeEvent>>,
+    }
+
+    impl FakeDriveSource {
+        pub fn new() -> Self {
+            Self {
+                root: Arc::new(tempfile::tempdir().expect("failed to create a temp dir")),
+                inner: Default::default(),
+            }
+        }
+
+        /// The directory that backs the card, created on demand
+        pub fn card(&self, serial: &str) -> PathBuf {
+            let path = self.root.path().join(serial);
+            fs::create_dir_all(&path).expect("failed to create the card directory");
+            path
+        }
+
+        pub fn insert(&self, serial: &str) {
+            self.attach(serial, true);
+        }
+
+        /// Mounts the directory as a non-removable disk
+        pub fn insert_fixed(&self, serial: &str) {
+            self.attach(serial, false);
+        }
+
+        fn attach(&self, serial: &str, removable: bool) {
+            let volume = Volume {
+                mount_path: self.card(serial),
+                file_system: FileSystem::ExFat,
+                label: None,
+                serial: Some(serial.into()),
+                total_bytes: 0,
+                free_bytes: 0,
+                removable,
+            };
+
+            let mut inner = self.inner.lock().unwrap();
+            inner.broadcast(VolumeEvent::VolumeAdded(volume.clone()));
+            inner.inserted.insert(serial.into(), volume);
+        }
+
+        pub fn remove(&self, serial: &str) {
+            let mut inner = self.inner.lock().unwrap();
+            if let Some(volume) = inner.inserted.remove(serial) {
+                inner.broadcast(VolumeEvent::VolumeRemoved(volume));
+            }
+        }
+    }
+
+    impl Inner {
+        fn broadcast(&mut self, event: VolumeEvent) {
+            self.watchers.retain(|tx| tx.send(event.clone()).is_ok());
+        }
+    }
+
+    impl DriveSource for FakeDriveSource {
+        fn watch(&self) -> VolumeWatcher {
+            let (tx, rx) = tokio_mpsc::unbounded_channel();
+
+            let mut inner = self.inner.lock().unwrap();
+            for volume in inner.inserted.values() {
+                let _ = tx.send(VolumeEvent::VolumeAdded(volume.clone()));
+            }
+            inner.watchers.push(tx);
+
+            rx.into()
+        }
+    }
+
+    /// A recording that isn't on any card, made `seconds` after 2024-03-26 15:00:00
+    pub fn recording(path: impl Into<PathBuf>, category: Category, seconds: i64) -> Recording {
+        let path = path.into();
+        Recording {
+            identity: FileIdentity {
+                card_serial: None,
+                relative_path: path.to_string_lossy().into_owned(),
+                size: 0,
+                mtime: 0,
+                hash: None,
+                recorded_at: None,
+                channel: None,
+            },
+            path,
+            category,
+            clip: ClipInfo {
+                recorded_at: NaiveDate::from_ymd_opt(2024, 3, 26)
+                    .unwrap()
+                    .and_hms_opt(15, 0, 0)
+                    .unwrap()
+                    + chrono::Duration::seconds(seconds),
+                source: TimestampSource::Filename,
+                channel: None,
+                sequence: None,
+            },
+            camera: "test".into(),
+            trip: vec![],
+        }
+    }
+
+    /// A destination that keeps the entries it was sent in memory
+    #[derive(Debug, Clone, Default)]
+    pub struct FakeDestination {
+        published: Arc<Mutex<Published>>,
+    }
+
+    #[derive(Debug, Default)]
+    struct Published {
+        entries: Vec<Entry>,
+        last_id: i32,
+        /// How long the upload of a file takes, by its name
+        upload_delays: HashMap<String, Duration>,
+        /// The files whose uploads hang without sending anything
+        stalled: HashSet<String>,
+        /// The names of the uploaded files, in the order their uploads finished
+        uploads: Vec<String>,
+    }
+
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub struct Entry {
+        pub id: i32,
+        /// The name of the file, `location` for location pins
+        pub name: String,
+        pub caption: String,
+        pub reply_to: Option<i32>,
+    }
+
+    #[derive(Debug)]
+    pub struct FakeMedia {
+        pub name: String,
+    }
+
+    impl FakeDestination {
+        pub fn new() -> Self {
+            Self::default()
+        }
+
+        /// The entries that are still published, in the order they were sent
+        pub fn entries(&self) -> Vec<Entry> {
+            self.published.lock().unwrap().entries.clone()
+        }
+
+        /// Makes the upload of the file take a while, e.g. as if it were bigger
+        pub fn delay_upload(&self, name: &str, delay: Duration) {
+            let mut published = self.published.lock().unwrap();
+            published.upload_delays.insert(name.into(), delay);
+        }
+
+        /// Makes the upload of the file hang without sending anything, as on a dead connection
+        pub fn stall_upload(&self, name: &str) {
+            self.published.lock().unwrap().stalled.insert(name.into());
+        }
+
+        /// The names of the uploaded files, in the order their uploads finished
+        pub fn uploads(&self) -> Vec<String> {
+            self.published.lock().unwrap().uploads.clone()
+        }
+
+        fn publish(&self, name: &str, caption: &str, reply_to: Option<i32>) -> i32 {
+            let mut published = self.published.lock().unwrap();
+            published.last_id += 1;
+            let id = published.last_id;
+            published.entries.push(Entry {
+                id,
+                name: name.into(),
+                caption: caption.into(),
+                reply_to,
+            });
+            id
+        }
+    }
+
+    impl Destination for FakeDestination {
+        type Media = FakeMedia;
+
+        fn video_info(_media: &FakeMedia) -> Option<&VideoInfo> {
+            None
+        }
+
+        fn max_file_size(&self, _settings: &Settings) -> u64 {
+            u64::MAX
+        }
+
+        fn max_caption_len(&self) -> usize {
+            1024
+        }
+
+        fn connection_failed(&self) {}
+
+        async fn ensure_connected(
+            &self,
+            _on_event: impl Fn(ConnectionEvent) + Send,
+        ) -> Result<(), DestinationErr> {
+            Ok(())
+        }
+
+        async fn upload(
+            &self,
+            path: PathBuf,
+            _kind: MediaKind<'_>,
+            on_progress: impl Fn(u64) + Send + Sync,
+        ) -> Result<FakeMedia, DestinationErr> {
+            let name = path.file_name().unwrap().to_string_lossy().to_string();
+            let (delay, stalled) = {
+                let published = self.published.lock().unwrap();
+                let delay = published.upload_delays.get(&name).copied();
+                (delay, published.stalled.contains(&name))
+            };
+            if stalled {
+                std::future::pending::<()>().await;
+            }
+
+            let size = tokio::fs::metadata(&path).await?.len();
+            if let Some(delay) = delay {
+                // a slow but steady upload, that reports its progress every second
+                let mut elapsed = Duration::ZERO;
+                while elapsed < delay {
+                    let step = (delay - elapsed).min(Duration::from_secs(1));
+                    tokio::time::sleep(step).await;
+                    elapsed += step;
+                    on_progress((size as f64 * elapsed.as_secs_f64() / delay.as_secs_f64()) as u64);
+                }
+            }
+            on_progress(size);
+
+            self.published.lock().unwrap().uploads.push(name.clone());
+            Ok(FakeMedia { name })
+        }
+
+        async fn send(
+            &self,
+            media: &FakeMedia,
+            caption: String,
+            reply_to: Option<i32>,
+        ) -> Result<i32, DestinationErr> {
+            Ok(self.publish(&media.name, &caption, reply_to))
+        }
+
+        async fn send_album(
+            &self,
+            media: &[&FakeMedia],
+            caption: String,
+        ) -> Result<Vec<Option<i32>>, DestinationErr> {
+            Ok(media
+                .iter()
+                .enumerate()
+                .map(|(idx, media)| {
+                    let caption = if idx == 0 { caption.as_str() } else { "" };
+                    Some(self.publish(&media.name, caption, None))
+                })
+                .collect())
+        }
+
+        async fn send_document(
+            &self,
+            name: String,
+            _mime_type: &str,
+            _bytes: &[u8],
+            reply_to: Option<i32>,
+        ) -> Result<i32, DestinationErr> {
+            Ok(self.publish(&name, "", reply_to))
+        }
+
+        async fn send_location(
+            &self,
+            _latitude: f64,
+            _longitude: f64,
+            reply_to: Option<i32>,
+        ) -> Result<(), DestinationErr> {
+            self.publish("location", "", reply_to);
+            Ok(())
+        }
+
+        async fn exists(&self, id: i32) -> Result<bool, DestinationErr> {
+            let published = self.published.lock().unwrap();
+            Ok(published.entries.iter().any(|entry| entry.id == id))
+        }
+
+        async fn delete(&self, ids: &[i32]) -> Result<(), DestinationErr> {
+            let mut published = self.published.lock().unwrap();
+            published.entries.retain(|entry| !ids.contains(&entry.id));
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        fs,
+        path::{Path, PathBuf},
+        sync::{Arc, Mutex},
+        time::Duration,
+    };
+
+    use tokio::sync::{mpsc as tokio_mpsc, watch, Semaphore};
+
+    use super::{
+        backoff, drive_upload_worker,
+        fake::{FakeDestination, FakeDriveSource, FakeMedia},
+        finish, finish_pair, is_pair, prepare, uploading, wait_for_cardv_drive, DriveUploader,
+        Prepared, Ready, Recording, Recovery, Summary, UploaderMsg, BASE_BACKOFF, MAX_BACKOFF,
+    };
+    use crate::{
+        caption,
+        clip::ClipInfo,
+        destination::{Destination, DestinationErr, MediaKind},
+        layout::{self, Category, SourceFolder},
+        ledger::{FileIdentity, Ledger, LedgerEntry, UploadStatus},
+        queue::{UploadOrder, UploadQueue},
+        settings::Settings,
+    };
+
+    /// A file on the card, as the uploader finds it
+    fn scanned(card: &Path, name: &str, category: Category, content: &str) -> Recording {
+        let path = card.join(name);
+        fs::write(&path, content).unwrap();
+        let metadata = fs::metadata(&path).unwrap();
+        let clip = ClipInfo::read(&path, &metadata).unwrap();
+
+        Recording {
+            category,
+            identity: FileIdentity {
+                recorded_at: Some(clip.recorded_at),
+                channel: clip.channel,
+                ..FileIdentity::new(None, card, &path, &metadata).unwrap()
+            },
+            clip,
+            path,
+            camera: "test".into(),
+            trip: vec![],
+        }
+    }
+
+    /// A video whose upload went through, in a single piece
+    fn uploaded(file: &Recording, recovery: Recovery) -> Ready<FakeMedia> {
+        let media = FakeMedia {
+            name: file.path.file_name().unwrap().to_string_lossy().to_string(),
+        };
+
+        (
+            file.clone(),
+            Ok(Prepared::Uploaded {
+                sent: vec![],
+                media: vec![media],
+                track: vec![],
+                recovery,
+                transcoded_hash: None,
+            }),
+        )
+    }
+
+    /// Backs up the card that is inserted, until the uploader is done
+    async fn backup(
+        destination: &FakeDestination,
+        source: &FakeDriveSource,
+        ledger_path: &Path,
+        last_upload: Option<PathBuf>,
+    ) -> Summary {
+        let (_settings_tx, settings) = watch::channel(Settings::default());
+        let mut uploader = DriveUploader::new(
+            destination.clone(),
+            source.clone(),
+            settings,
+            ledger_path.to_path_buf(),
+            last_upload,
+        );
+
+        tokio::time::timeout(Duration::from_secs(10), async {
+            loop {
+                match uploader.try_recv() {
+                    Some(UploaderMsg::Done(summary)) => return summary,
+                    Some(
+                        msg @ (UploaderMsg::BadFileSystem
+                        | UploaderMsg::BadLedger(_)
+                        | UploaderMsg::Interrupted(_)),
+                    ) => panic!("the upload has failed: {msg:?}"),
+                    Some(_) => {}
+                    None => tokio::time::sleep(Duration::from_millis(10)).await,
+                }
+            }
+        })
+        .await
+        .expect("the upload should be done by now")
+    }
+
+    #[tokio::test]
+    async fn detect_inserted_cardv_drive() {
+        let source = FakeDriveSource::new();
+        source.insert("unrelated");
+        // a fixed disk that happens to have the same folders
+        fs::create_dir_all(source.card("disk").join("CARDV").join("Movie")).unwrap();
+        source.insert_fixed("disk");
+        fs::create_dir_all(source.card("cardv").join("CARDV").join("Movie")).unwrap();
+
+        let wait = tokio::spawn({
+            let source = source.clone();
+            async move { wait_for_cardv_drive(&source, &layout::builtin_profiles()).await }
+        });
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(!wait.is_finished());
+
+        source.insert("cardv");
+        let drive = wait.await.unwrap().expect("the source was not closed");
+        assert_eq!(drive.volume.serial.as_deref(), Some("cardv"));
+        assert_eq!(drive.profile, "Novatek");
+        assert_eq!(
+            drive.folders,
+            vec![SourceFolder {
+                path: source.card("cardv").join("CARDV").join("Movie"),
+                category: Category::Normal,
+            }]
+        );
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn upload_to_destination() {
+        let source = FakeDriveSource::new();
+        let card = source.card("cardv").join("CARDV");
+        fs::create_dir_all(card.join("Movie")).unwrap();
+        fs::create_dir_all(card.join("Photo")).unwrap();
+        fs::write(
+            card.join("Photo").join("2024_0326_153012_001F.JPG"),
+            "first",
+        )
+        .unwrap();
+        fs::write(
+            card.join("Photo").join("2024_0326_153112_002F.JPG"),
+            "second",
+        )
+        .unwrap();
+        // the same photo under another name
+        fs::write(
+            card.join("Photo").join("2024_0326_153212_003F.JPG"),
+            "first",
+        )
+        .unwrap();
+        // cut short by a power loss, with nothing to repair it from
+        fs::write(
+            card.join("Movie").join("2024_0326_153012_001F.MP4"),
+            "broken",
+        )
+        .unwrap();
+        source.insert("cardv");
+
+        let ledger_dir = tempfile::tempdir().unwrap();
+        let ledger_path = ledger_dir.path().join("ledger.jsonl");
+        let destination = FakeDestination::new();
+
+        let summary = backup(&destination, &source, &ledger_path, None).await;
+        assert_eq!(
+            (summary.uploaded, summary.duplicates, summary.unrecoverable),
+            (3, 1, 1)
+        );
+        // only one of the identical photos is sent
+        let entries = destination.entries();
+        assert_eq!(entries.len(), 3);
+        let broken = entries
+            .iter()
+            .find(|entry| entry.name.ends_with(".MP4"))
+            .unwrap();
+        assert!(broken.caption.ends_with("#unrecoverable"));
+
+        // a copy of a photo that was deleted from the destination is uploaded again,
+        // and everything that was already backed up is left alone
+        let second = entries
+            .iter()
+            .find(|entry| entry.name == "2024_0326_153112_002F.JPG")
+            .unwrap();
+        destination.delete(&[second.id]).await.unwrap();
+        fs::write(
+            card.join("Photo").join("2024_0326_153312_004F.JPG"),
+            "second",
+        )
+        .unwrap();
+
+        let summary = backup(&destination, &source, &ledger_path, None).await;
+        assert_eq!((summary.uploaded, summary.duplicates), (1, 0));
+        assert_eq!(
+            destination.entries().last().unwrap().name,
+            "2024_0326_153312_004F.JPG"
+        );
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn migrate_last_upload() {
+        let source = FakeDriveSource::new();
+        let photos = source.card("cardv").join("CARDV").join("Photo");
+        fs::create_dir_all(&photos).unwrap();
+        for (name, content) in [
+            ("2024_0326_153012_001F.JPG", "first"),
+            ("2024_0326_153112_002F.JPG", "second"),
+            ("2024_0326_153212_003F.JPG", "third"),
+        ] {
+            fs::write(photos.join(name), content).unwrap();
+        }
+        source.insert("cardv");
+
+        let ledger_dir = tempfile::tempdir().unwrap();
+        let ledger_path = ledger_dir.path().join("ledger.jsonl");
+        let destination = FakeDestination::new();
+
+        // the old uploader got up to the second photo
+        let last_upload = photos.join("2024_0326_153112_002F.JPG");
+        let summary = backup(&destination, &source, &ledger_path, Some(last_upload)).await;
+        assert_eq!(summary.uploaded, 1);
+        assert_eq!(
+            destination.entries().last().unwrap().name,
+            "2024_0326_153212_003F.JPG"
+        );
+
+        // the ledger remembers the files the old uploader sent
+        let summary = backup(&destination, &source, &ledger_path, None).await;
+        assert_eq!(summary.uploaded, 0);
+        assert_eq!(destination.entries().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn upload_the_unsent_segments_of_a_trip() {
+        let card = tempfile::tempdir().unwrap();
+        let segments = [
+            ("2024_0326_150000_001F.MP4", "first"),
+            ("2024_0326_150100_002F.MP4", "second"),
+            ("2024_0326_150200_003F.MP4", "third"),
+        ]
+        .map(|(name, content)| scanned(card.path(), name, Category::Normal, content));
+        let trip = Recording {
+            trip: segments[1..].to_vec(),
+            ..segments[0].clone()
+        };
+
+        let ledger_dir = tempfile::tempdir().unwrap();
+        let ledger = Ledger::open(&ledger_dir.path().join("ledger.jsonl")).unwrap();
+        let ledger = Arc::new(Mutex::new(ledger));
+        let destination = Arc::new(FakeDestination::new());
+        let (tx, _rx) = tokio_mpsc::unbounded_channel();
+        let prepare = |trip: Recording| {
+            let (destination, ledger, tx) = (destination.clone(), ledger.clone(), tx.clone());
+            async move {
+                let permits = Arc::new(Semaphore::new(1));
+                let settings = Arc::new(Settings::default());
+                prepare(destination, ledger, permits, trip, 0, settings, tx).await
+            }
+        };
+
+        // the first clip was sent on its own, before the camera locked it with the next ones
+        let media = FakeMedia {
+            name: "first".into(),
+        };
+        let message_id = destination.send(&media, String::new(), None).await.unwrap();
+        let entry = LedgerEntry::new(
+            segments[0].identity.clone(),
+            UploadStatus::Uploaded,
+            Some(message_id),
+        );
+        ledger.lock().unwrap().record(entry).unwrap();
+
+        let (_, file, prepared) = prepare(trip.clone()).await;
+        assert_eq!(file.path, segments[1].path);
+        let trip_paths = file.trip.iter().map(|segment| &segment.path);
+        assert!(trip_paths.eq([&segments[2].path]));
+
+        let mut summary = Summary::default();
+        finish(
+            &*destination,
+            &ledger,
+            &mut summary,
+            file,
+            prepared,
+            "",
+            &tx,
+        )
+        .await
+        .unwrap();
+        assert_eq!((summary.uploaded, summary.duplicates), (2, 1));
+        let status = |segment: &Recording| {
+            let ledger = ledger.lock().unwrap();
+            let entry = ledger.get(&segment.identity).unwrap();
+            (entry.status, entry.message_id)
+        };
+        assert_eq!(
+            status(&segments[0]),
+            (UploadStatus::Duplicate, Some(message_id))
+        );
+        assert_eq!(status(&segments[1]).0, UploadStatus::Uploaded);
+        assert_eq!(status(&segments[2]).0, UploadStatus::Uploaded);
+
+        // only a trip whose every segment was sent is a duplicate
+        let (_, _, prepared) = prepare(trip).await;
+        assert!(matches!(prepared, Ok(Prepared::Duplicate(sent)) if sent.len() == 3));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn time_out_only_stalled_uploads() {
+        let card = tempfile::tempdir().unwrap();
+        let name = "2024_0326_150000_001F.MP4";
+        let file = scanned(card.path(), name, Category::Normal, "clip");
+        let destination = FakeDestination::new();
+        let (tx, _rx) = tokio_mpsc::unbounded_channel();
+        let upload = || {
+            uploading(
+                &destination,
+                &file,
+                &tx,
+                &file.path,
+                MediaKind::Document,
+                &|_| {},
+            )
+        };
+
+        // far longer than any fixed limit, but sending all along
+        destination.delay_upload(name, Duration::from_secs(60 * 60));
+        assert!(upload().await.is_ok());
+        assert_eq!(destination.uploads(), [name]);
+
+        destination.stall_upload(name);
+        assert!(matches!(upload().await, Err(DestinationErr::Timeout)));
+    }
+
+    #[test]
+    fn backoff_grows_up_to_a_cap() {
+        // the jitter keeps every delay between half and all of its step
+        for _ in 0..100 {
+            let first = backoff(0);
+            assert!(BASE_BACKOFF / 2 <= first && first <= BASE_BACKOFF);
+
+            let third = backoff(2);
+            assert!(BASE_BACKOFF * 2 <= third && third <= BASE_BACKOFF * 4);
+
+            for attempt in [10, 31, u32::MAX] {
+                let capped = backoff(attempt);
+                assert!(MAX_BACKOFF / 2 <= capped && capped <= MAX_BACKOFF);
+            }
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn send_in_order_while_uploading_concurrently() {
+        let card = tempfile::tempdir().unwrap();
+        let photos = [
+            scanned(
+                card.path(),
+                "2024_0326_153012_001F.JPG",
+                Category::Photo,
+                "first",
+            ),
+            scanned(
+                card.path(),
+                "2024_0326_153112_002F.JPG",
+                Category::Photo,
+                "second",
+            ),
+            scanned(
+                card.path(),
+                "2024_0326_153212_003F.JPG",
+                Category::Photo,
+                "third",
+            ),
+        ];
+        let names = photos
+            .iter()
+            .map(|photo| {
+                photo
+                    .path
+                    .file_name()
+                    .unwrap()
+                    .to_string_lossy()
+                    .to_string()
+            })
+            .collect::<Vec<_>>();
+
+        // the first photo is the slowest to upload, and the last one the fastest
+        let destination = FakeDestination::new();
+        destination.delay_upload(&names[0], Duration::from_millis(400));
+        destination.delay_upload(&names[1], Duration::from_millis(200));
+
+        let ledger_dir = tempfile::tempdir().unwrap();
+        let ledger_path = ledger_dir.path().join("ledger.jsonl");
+        let mut queue = UploadQueue::new(UploadOrder::default());
+        queue.extend(photos.iter().cloned());
+        let settings = Settings {
+            concurrent_uploads: 3,
+            ..Default::default()
+        };
+        let (tx, mut rx) = tokio_mpsc::unbounded_channel();
+        drive_upload_worker(
+            destination.clone(),
+            Ledger::open(&ledger_path).unwrap(),
+            queue,
+            0,
+            &settings,
+            tx,
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(
+            destination.uploads(),
+            [names[2].as_str(), names[1].as_str(), names[0].as_str()]
+        );
+        let entries = destination.entries();
+        assert_eq!(
+            entries
+                .iter()
+                .map(|entry| entry.name.as_str())
+                .collect::<Vec<_>>(),
+            names
+        );
+        let completed = std::iter::from_fn(|| rx.try_recv().ok())
+            .filter_map(|msg| match msg {
+                UploaderMsg::Completed(current) => Some(current),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(completed, [0, 1, 2]);
+
+        let ledger = Ledger::open(&ledger_path).unwrap();
+        for (photo, entry) in photos.iter().zip(&entries) {
+            let recorded = ledger.get(&photo.identity).unwrap();
+            assert_eq!(recorded.status, UploadStatus::Uploaded);
+            assert_eq!(recorded.message_id, Some(entry.id));
+        }
+    }
+
+    #[tokio::test]
+    async fn send_both_lenses_as_an_album() {
+        let card = tempfile::tempdir().unwrap();
+        let video = |name, content| scanned(card.path(), name, Category::Normal, content);
+        let front = video("2024_0326_153012_001F.MP4", "front");
+        let rear = video("2024_0326_153012_001R.MP4", "rear");
+        let later_front = video("2024_0326_153112_002F.MP4", "later front");
+        let later_rear = video("2024_0326_153112_002R.MP4", "later rear");
+
+        assert!(is_pair(&front, &rear));
+        assert!(is_pair(&later_rear, &later_front));
+        // a clip without its other lens is sent alone
+        assert!(!is_pair(&front, &later_rear));
+        assert!(!is_pair(&front, &later_front));
+        let photo = |name, content| scanned(card.path(), name, Category::Photo, content);
+        assert!(!is_pair(
+            &photo("2024_0326_153012_001F.JPG", "front"),
+            &photo("2024_0326_153012_001R.JPG", "rear")
+        ));
+
+        let destination = FakeDestination::new();
+        let ledger_dir = tempfile::tempdir().unwrap();
+        let ledger = Mutex::new(Ledger::open(&ledger_dir.path().join("ledger.jsonl")).unwrap());
+        let mut summary = Summary::default();
+        let (tx, _rx) = tokio_mpsc::unbounded_channel();
+
+        finish_pair(
+            &destination,
+            &ledger,
+            &mut summary,
+            [
+                uploaded(&front, Recovery::Intact),
+                uploaded(&rear, Recovery::Intact),
+            ],
+            caption::DEFAULT_TEMPLATE,
+            &tx,
+        )
+        .await
+        .unwrap();
+        // only the first item of an album has a caption
+        let entries = destination.entries();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].name, "2024_0326_153012_001F.MP4");
+        assert!(!entries[0].caption.is_empty());
+        assert_eq!(entries[1].name, "2024_0326_153012_001R.MP4");
+        assert!(entries[1].caption.is_empty());
+        for (file, entry) in [&front, &rear].into_iter().zip(&entries) {
+            let recorded = ledger.lock().unwrap().get(&file.identity).cloned();
+            assert_eq!(recorded.unwrap().message_id, Some(entry.id));
+        }
+
+        // a clip that is sent as a plain file can't be part of an album
+        finish_pair(
+            &destination,
+            &ledger,
+            &mut summary,
+            [
+                uploaded(&later_front, Recovery::Intact),
+                uploaded(&later_rear, Recovery::Unrecoverable),
+            ],
+            caption::DEFAULT_TEMPLATE,
+            &tx,
+        )
+        .await
+        .unwrap();
+        let entries = destination.entries();
+        assert_eq!(entries.len(), 4);
+        assert!(!entries[2].caption.is_empty());
+        assert!(entries[3].caption.ends_with("#unrecoverable"));
+    }
+}
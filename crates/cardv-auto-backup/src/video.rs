@@ -0,0 +1,120 @@
+//! What telegram (and the captions) need to know about a video
+
+use std::{path::Path, time::Duration};
+
+use mp4::Mp4Track;
+
+use crate::mp4_file::{self, Mp4Err};
+
+const VIDEO_HANDLER: [u8; 4] = *b"vide";
+const AUDIO_HANDLER: [u8; 4] = *b"soun";
+
+#[derive(thiserror::Error, Debug)]
+pub enum VideoErr {
+    #[error(transparent)]
+    File(#[from] Mp4Err),
+
+    #[error("the mp4 has no video track")]
+    NoVideoTrack,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct VideoInfo {
+    pub duration: Duration,
+    /// As the video is displayed, i.e. after the rotation
+    pub width: u16,
+    pub height: u16,
+    /// Clockwise, in degrees
+    pub rotation: u16,
+    /// e.g. `h264`, or the sample entry type of codecs the mp4 crate doesn't know
+    pub codec: String,
+    pub fps: f64,
+    /// Bits per second
+    pub bitrate: u32,
+    pub has_audio: bool,
+}
+
+impl VideoInfo {
+    /// Reads the info from the video track (by its `vide` handler), rather than
+    /// whatever track comes first, which may be the audio or GPS data
+    pub fn read(path: &Path) -> Result<Self, VideoErr> {
+        let mp4 = mp4_file::open(path)?;
+
+        let mut video_tracks = mp4
+            .tracks()
+            .values()
+            .filter(|track| track.trak.mdia.hdlr.handler_type.value == VIDEO_HANDLER)
+            .collect::<Vec<_>>();
+        // some cameras add a low resolution preview track, the main one is the biggest
+        video_tracks.sort_by_key(|track| u32::from(track.width()) * u32::from(track.height()));
+        let track = video_tracks.pop().ok_or(VideoErr::NoVideoTrack)?;
+
+        let duration = track_duration(track).unwrap_or_else(|| {
+            // fall back to the movie's duration
+            let mvhd = &mp4.moov.mvhd;
+            Duration::from_secs_f64(mvhd.duration as f64 / mvhd.timescale.max(1) as f64)
+        });
+        let matrix = &track.trak.tkhd.matrix;
+        let rotation = rotation(matrix.a, matrix.b);
+        let (width, height) = match rotation {
+            90 | 270 => (track.height(), track.width()),
+            _ => (track.width(), track.height()),
+        };
+        let codec = track
+            .media_type()
+            .map(|media_type| media_type.to_string())
+            .or_else(|_| track.box_type().map(|box_type| box_type.to_string()))
+            .unwrap_or_default();
+        let fps = if duration.is_zero() {
+            0.0
+        } else {
+            track.sample_count() as f64 / duration.as_secs_f64()
+        };
+
+        Ok(Self {
+            duration,
+            width,
+            height,
+            rotation,
+            codec,
+            fps,
+            bitrate: track.bitrate(),
+            has_audio: mp4
+                .tracks()
+                .values()
+                .any(|track| track.trak.mdia.hdlr.handler_type.value == AUDIO_HANDLER),
+        })
+    }
+}
+
+/// The duration in the track's own timescale, which the movie's may differ from
+fn track_duration(track: &Mp4Track) -> Option<Duration> {
+    let mdhd = &track.trak.mdia.mdhd;
+    (mdhd.timescale > 0 && mdhd.duration > 0)
+        .then(|| Duration::from_secs_f64(mdhd.duration as f64 / mdhd.timescale as f64))
+}
+
+/// The rotation in the track header's transformation matrix, to the nearest right angle
+///
+/// the matrix is `[a b u; c d v; x y w]`, with `a` and `b` being the cos and sin in 16.16
+fn rotation(a: i32, b: i32) -> u16 {
+    let degrees = (b as f64).atan2(a as f64).to_degrees();
+    ((degrees / 90.0).round() as i32 * 90).rem_euclid(360) as u16
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rotation_from_matrix() {
+        const ONE: i32 = 0x0001_0000;
+
+        assert_eq!(rotation(ONE, 0), 0);
+        assert_eq!(rotation(0, ONE), 90);
+        assert_eq!(rotation(-ONE, 0), 180);
+        assert_eq!(rotation(0, -ONE), 270);
+        // rounding errors of the camera
+        assert_eq!(rotation(3, ONE), 90);
+    }
+}
@@ -1,27 +1,56 @@
-use windows::Win32::Storage::FileSystem::GetLogicalDrives;
+use std::path::PathBuf;
 
-/// Lists all the logical drives that are currently mounted
-pub fn list_all_logical_drives() -> Vec<char> {
-    // Safety: !
-    let mut mask = unsafe { GetLogicalDrives() };
+#[cfg(target_os = "linux")]
+mod linux;
+mod watch;
+#[cfg(windows)]
+mod windows;
 
-    let mut drives = vec![];
-    for ch in 'A'..='Z' {
-        if mask & 1 != 0 {
-            drives.push(ch);
-        }
-        mask >>= 1;
-    }
+pub use watch::{watch_volumes, VolumeEvent, VolumeWatcher};
 
-    drives
+/// A mounted logical volume
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Volume {
+    /// Where the volume is mounted (e.g. `E:\` or `/media/user/CARD`)
+    pub mount_path: PathBuf,
+    pub file_system: FileSystem,
+    pub label: Option<String>,
+    /// The volume serial number on windows, and the filesystem uuid on linux
+    ///
+    /// unlike the mount path, this stays the same
+    /// every time the same card is inserted.
+    pub serial: Option<String>,
+    pub total_bytes: u64,
+    pub free_bytes: u64,
+    pub removable: bool,
 }
 
-#[cfg(test)]
-mod tests {
-    use crate::list_all_logical_drives;
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum FileSystem {
+    Fat32,
+    ExFat,
+    Other(String),
+}
 
-    #[test]
-    fn check_for_c_drive() {
-        assert!(list_all_logical_drives().contains(&'C'))
+impl FileSystem {
+    /// Map the name reported by the OS (e.g. `FAT32`, `vfat`, `exfat`)
+    pub fn from_name(name: &str) -> Self {
+        match name.to_lowercase().as_str() {
+            "fat32" | "fat" | "vfat" | "msdos" => Self::Fat32,
+            "exfat" => Self::ExFat,
+            _ => Self::Other(name.into()),
+        }
     }
 }
+
+/// Lists all the volumes that are currently mounted
+///
+/// on windows these are all the logical drives (e.g. `C:\`), while
+/// on linux only volumes that are backed by a block device are reported.
+pub fn list_volumes() -> Vec<Volume> {
+    #[cfg(windows)]
+    return windows::list_volumes();
+
+    #[cfg(target_os = "linux")]
+    return linux::list_volumes();
+}
@@ -0,0 +1,249 @@
+use std::{
+    ffi::CString,
+    fs, io, mem,
+    os::{fd::AsRawFd, unix::ffi::OsStrExt},
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use crate::{FileSystem, Volume};
+
+const MOUNTINFO: &str = "/proc/self/mountinfo";
+const SYS_BLOCK: &str = "/sys/block";
+const SYS_CLASS_BLOCK: &str = "/sys/class/block";
+const BY_LABEL: &str = "/dev/disk/by-label";
+const BY_UUID: &str = "/dev/disk/by-uuid";
+
+pub fn list_volumes() -> Vec<Volume> {
+    let Ok(mountinfo) = fs::read_to_string(MOUNTINFO) else {
+        return vec![];
+    };
+
+    parse_mountinfo(&mountinfo)
+        .into_iter()
+        .filter_map(|mount| {
+            // the source may be a symlink (e.g. /dev/disk/by-uuid/..)
+            let device = fs::canonicalize(&mount.source).ok()?;
+            if !device.starts_with("/dev/") {
+                return None;
+            }
+
+            let (total_bytes, free_bytes) = disk_space(&mount.mount_point).unwrap_or_default();
+            Some(Volume {
+                file_system: FileSystem::from_name(&mount.fs_type),
+                label: find_device_link(BY_LABEL, &device),
+                serial: find_device_link(BY_UUID, &device),
+                total_bytes,
+                free_bytes,
+                removable: is_removable(&device),
+                mount_path: mount.mount_point,
+            })
+        })
+        .collect()
+}
+
+/// Watches the mount table for changes
+///
+/// the kernel marks `/proc/self/mountinfo` with a priority
+/// event (POLLPRI) every time a filesystem is mounted or unmounted.
+#[derive(Debug)]
+pub struct MountWatcher {
+    mountinfo: fs::File,
+}
+
+impl MountWatcher {
+    pub fn new() -> io::Result<Self> {
+        Ok(Self {
+            mountinfo: fs::File::open(MOUNTINFO)?,
+        })
+    }
+
+    /// Blocks until the mount table changes or the timeout lapses
+    ///
+    /// returns whether the mount table has changed
+    pub fn wait(&mut self, timeout: Duration) -> io::Result<bool> {
+        let mut fd = libc::pollfd {
+            fd: self.mountinfo.as_raw_fd(),
+            events: libc::POLLPRI,
+            revents: 0,
+        };
+        let timeout = timeout.as_millis().try_into().unwrap_or(libc::c_int::MAX);
+
+        // Safety: the pollfd outlives the call, and we pass exactly one
+        match unsafe { libc::poll(&mut fd, 1, timeout) } {
+            0 => Ok(false),
+            n if n > 0 => Ok(fd.revents & (libc::POLLPRI | libc::POLLERR) != 0),
+            _ => {
+                let err = io::Error::last_os_error();
+                match err.kind() {
+                    io::ErrorKind::Interrupted => Ok(false),
+                    _ => Err(err),
+                }
+            }
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+struct Mount {
+    mount_point: PathBuf,
+    fs_type: String,
+    source: String,
+}
+
+/// Parses the content of `/proc/<pid>/mountinfo`
+///
+/// see `proc(5)` for the exact format, entries that
+/// can not be parsed are silently ignored.
+fn parse_mountinfo(content: &str) -> Vec<Mount> {
+    content
+        .lines()
+        .filter_map(|line| {
+            let fields = line.split_whitespace().collect::<Vec<_>>();
+            // the optional fields are terminated by a single hyphen
+            let separator = fields.iter().position(|field| *field == "-")?;
+
+            Some(Mount {
+                mount_point: PathBuf::from(unescape(fields.get(4)?)),
+                fs_type: fields.get(separator + 1)?.to_string(),
+                source: unescape(fields.get(separator + 2)?),
+            })
+        })
+        .collect()
+}
+
+/// The kernel escapes spaces, tabs, newlines and backslashes as octal sequences (e.g. `\040`)
+fn unescape(field: &str) -> String {
+    let mut out = String::with_capacity(field.len());
+    let mut rest = field;
+    while let Some(idx) = rest.find('\\') {
+        out.push_str(&rest[..idx]);
+        let escaped = rest.get(idx + 1..idx + 4);
+        match escaped.and_then(|code| u8::from_str_radix(code, 8).ok()) {
+            Some(ch) => {
+                out.push(ch as char);
+                rest = &rest[idx + 4..];
+            }
+            None => {
+                out.push('\\');
+                rest = &rest[idx + 1..];
+            }
+        }
+    }
+    out.push_str(rest);
+
+    out
+}
+
+/// Checks whether a block device is removable
+fn is_removable(device: &Path) -> bool {
+    let Some(name) = device.file_name() else {
+        return false;
+    };
+    let Ok(mut sys) = fs::canonicalize(Path::new(SYS_CLASS_BLOCK).join(name)) else {
+        return false;
+    };
+    // partitions live under their parent disk, which holds the 'removable' flag
+    if sys.join("partition").exists() {
+        sys.pop();
+    }
+    let Some(disk) = sys.file_name() else {
+        return false;
+    };
+
+    let removable = fs::read_to_string(Path::new(SYS_BLOCK).join(disk).join("removable"))
+        .map(|flag| flag.trim() == "1")
+        .unwrap_or(false);
+
+    // built-in sd-card readers (mmcblk) and many usb card readers
+    // report themselves as non-removable even though they are
+    removable
+        || disk.to_string_lossy().starts_with("mmcblk")
+        || sys.to_string_lossy().contains("/usb")
+}
+
+/// Search a udev symlink directory for the link pointing at the device
+///
+/// the name of the link is the value (e.g. the label or the uuid).
+fn find_device_link(dir: &str, device: &Path) -> Option<String> {
+    fs::read_dir(dir)
+        .ok()?
+        .filter_map(Result::ok)
+        .find(|link| fs::canonicalize(link.path()).is_ok_and(|target| target == device))
+        .map(|link| unescape_udev(&link.file_name().to_string_lossy()))
+}
+
+/// udev escapes unsafe characters in link names as hex sequences (e.g. `\x20`)
+fn unescape_udev(name: &str) -> String {
+    let mut out = vec![];
+    let mut rest = name.as_bytes();
+    while let Some((&byte, tail)) = rest.split_first() {
+        let escaped = tail
+            .strip_prefix(b"x")
+            .and_then(|hex| hex.get(..2))
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match escaped {
+            Some(ch) if byte == b'\\' => {
+                out.push(ch);
+                rest = &tail[3..];
+            }
+            _ => {
+                out.push(byte);
+                rest = tail;
+            }
+        }
+    }
+
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+/// Returns the (total, free) bytes of the filesystem mounted at the path
+fn disk_space(mount_point: &Path) -> Option<(u64, u64)> {
+    let path = CString::new(mount_point.as_os_str().as_bytes()).ok()?;
+
+    // Safety: statvfs is plain old data, and is only read after a successful call
+    let mut stat: libc::statvfs = unsafe { mem::zeroed() };
+    if unsafe { libc::statvfs(path.as_ptr(), &mut stat) } != 0 {
+        return None;
+    }
+
+    let block = stat.f_frsize as u64;
+    Some((stat.f_blocks as u64 * block, stat.f_bavail as u64 * block))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::{parse_mountinfo, unescape_udev, Mount};
+
+    #[test]
+    fn parse_removable_mount() {
+        let mountinfo = "\
+            36 35 98:0 /mnt1 /mnt2 rw,noatime master:1 - ext3 /dev/root rw,errors=continue\n\
+            120 29 179:1 / /media/user/CARD\\040A rw,nosuid,nodev shared:60 - vfat /dev/mmcblk0p1 rw,fmask=0022\n";
+
+        assert_eq!(
+            parse_mountinfo(mountinfo),
+            vec![
+                Mount {
+                    mount_point: PathBuf::from("/mnt2"),
+                    fs_type: "ext3".into(),
+                    source: "/dev/root".into(),
+                },
+                Mount {
+                    mount_point: PathBuf::from("/media/user/CARD A"),
+                    fs_type: "vfat".into(),
+                    source: "/dev/mmcblk0p1".into(),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn unescape_label() {
+        assert_eq!(unescape_udev("NO\\x20NAME"), "NO NAME");
+        assert_eq!(unescape_udev("CARD\\x2"), "CARD\\x2");
+    }
+}
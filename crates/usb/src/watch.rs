@@ -0,0 +1,188 @@
+use std::{collections::HashSet, path::Path, thread, time::Duration};
+
+use tokio::sync::mpsc as tokio_mpsc;
+
+use crate::{list_volumes, Volume};
+
+/// How often the volumes are re-listed when the OS can't notify us about changes
+const POLLING_INTERVAL: Duration = Duration::from_secs(1);
+/// The longest time the watcher thread may block before noticing it was dropped
+#[cfg(target_os = "linux")]
+const CANCEL_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum VolumeEvent {
+    VolumeAdded(Volume),
+    VolumeRemoved(Volume),
+}
+
+/// An async stream of volume hotplug events
+///
+/// the volumes that are already mounted when the watcher is created
+/// are reported as added, dropping the watcher stops watching.
+#[derive(Debug)]
+pub struct VolumeWatcher {
+    rx: tokio_mpsc::UnboundedReceiver<VolumeEvent>,
+}
+
+impl VolumeWatcher {
+    /// Watch by re-listing the volumes every interval lapse
+    ///
+    /// this works everywhere, but it's slower to react than [`watch_volumes`].
+    pub fn polling(interval: Duration) -> Self {
+        Self::spawn(move |_| {
+            thread::sleep(interval);
+            true
+        })
+    }
+
+    /// Wait for the next event
+    ///
+    /// returns None only if the watcher thread has died
+    pub async fn next(&mut self) -> Option<VolumeEvent> {
+        self.rx.recv().await
+    }
+
+    /// Spawns a thread that reports the changes in the mounted volumes
+    ///
+    /// 'wait' should block until the volumes may have changed, and return
+    /// false to give up, the thread also exits once the watcher is dropped.
+    fn spawn(
+        mut wait: impl FnMut(&tokio_mpsc::UnboundedSender<VolumeEvent>) -> bool + Send + 'static,
+    ) -> Self {
+        let (tx, rx) = tokio_mpsc::unbounded_channel();
+        thread::spawn(move || {
+            let mut known = vec![];
+
+            loop {
+                let current = list_volumes();
+                for event in diff(&known, &current) {
+                    if tx.send(event).is_err() {
+                        return;
+                    }
+                }
+                known = current;
+
+                if tx.is_closed() || !wait(&tx) {
+                    return;
+                }
+            }
+        });
+
+        Self { rx }
+    }
+}
+
+/// The events that turn the `known` volumes into the `current` ones
+///
+/// removals come first, so a card that was swapped at the same mount path
+/// is reported as removed before the new one is added.
+fn diff(known: &[Volume], current: &[Volume]) -> Vec<VolumeEvent> {
+    // the serial tells apart two cards that were mounted at the same path
+    fn key(volume: &Volume) -> (&Path, Option<&str>) {
+        (&volume.mount_path, volume.serial.as_deref())
+    }
+    let known_keys = known.iter().map(key).collect::<HashSet<_>>();
+    let current_keys = current.iter().map(key).collect::<HashSet<_>>();
+
+    let removed = known
+        .iter()
+        .filter(|volume| !current_keys.contains(&key(volume)))
+        .map(|volume| VolumeEvent::VolumeRemoved(volume.clone()));
+    let added = current
+        .iter()
+        .filter(|volume| !known_keys.contains(&key(volume)))
+        .map(|volume| VolumeEvent::VolumeAdded(volume.clone()));
+
+    removed.chain(added).collect()
+}
+
+/// Adapt any other source of events (e.g. a fake in tests) into a watcher
+impl From<tokio_mpsc::UnboundedReceiver<VolumeEvent>> for VolumeWatcher {
+    fn from(rx: tokio_mpsc::UnboundedReceiver<VolumeEvent>) -> Self {
+        Self { rx }
+    }
+}
+
+/// Watch the mounted volumes, using OS notifications where possible
+///
+/// on linux the mount table is watched with `poll(2)`,
+/// on other platforms this falls back to polling.
+pub fn watch_volumes() -> VolumeWatcher {
+    #[cfg(target_os = "linux")]
+    match crate::linux::MountWatcher::new() {
+        Ok(watcher) => {
+            let mut watcher = Some(watcher);
+            return VolumeWatcher::spawn(move |tx| {
+                while let Some(mount_watcher) = watcher.as_mut() {
+                    match mount_watcher.wait(CANCEL_CHECK_INTERVAL) {
+                        Ok(true) => return true,
+                        Ok(false) if tx.is_closed() => return false,
+                        Ok(false) => {}
+                        Err(err) => {
+                            tracing::warn!(
+                                ?err,
+                                "failed to watch the mount table, polling instead"
+                            );
+                            watcher = None;
+                        }
+                    }
+                }
+
+                thread::sleep(POLLING_INTERVAL);
+                true
+            });
+        }
+        Err(err) => tracing::warn!(?err, "failed to watch the mount table, polling instead"),
+    }
+
+    VolumeWatcher::polling(POLLING_INTERVAL)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::{diff, VolumeEvent};
+    use crate::{FileSystem, Volume};
+
+    fn volume(mount_path: &str, serial: &str) -> Volume {
+        Volume {
+            mount_path: PathBuf::from(mount_path),
+            file_system: FileSystem::Fat32,
+            label: None,
+            serial: Some(serial.into()),
+            total_bytes: 0,
+            free_bytes: 0,
+            removable: true,
+        }
+    }
+
+    #[test]
+    fn diff_volume_lists() {
+        let card = || volume("/media/user/CARD", "1234-ABCD");
+        let other = || volume("/media/user/OTHER", "5678-EF01");
+
+        assert_eq!(diff(&[], &[card()]), vec![VolumeEvent::VolumeAdded(card())]);
+        assert_eq!(diff(&[card()], &[card()]), vec![]);
+        assert_eq!(
+            diff(&[card(), other()], &[other()]),
+            vec![VolumeEvent::VolumeRemoved(card())]
+        );
+
+        // the free space changes while a card is being written to
+        let mut fuller = card();
+        fuller.free_bytes = 1;
+        assert_eq!(diff(&[card()], &[fuller]), vec![]);
+
+        // another card, mounted at the same path
+        let swapped = || volume("/media/user/CARD", "9999-0000");
+        assert_eq!(
+            diff(&[card()], &[swapped()]),
+            vec![
+                VolumeEvent::VolumeRemoved(card()),
+                VolumeEvent::VolumeAdded(swapped()),
+            ]
+        );
+    }
+}
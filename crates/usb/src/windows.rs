@@ -0,0 +1,107 @@
+use std::path::PathBuf;
+
+use windows::{
+    core::HSTRING,
+    Win32::{
+        Storage::FileSystem::{
+            GetDiskFreeSpaceExW, GetDriveTypeW, GetLogicalDrives, GetVolumeInformationW,
+        },
+        System::WindowsProgramming::DRIVE_REMOVABLE,
+    },
+};
+
+use crate::{FileSystem, Volume};
+
+// MAX_PATH + 1, as documented by 'GetVolumeInformationW'
+const NAME_BUFFER_LEN: usize = 261;
+
+pub fn list_volumes() -> Vec<Volume> {
+    // Safety: !
+    let mut mask = unsafe { GetLogicalDrives() };
+
+    let mut volumes = vec![];
+    for ch in 'A'..='Z' {
+        if mask & 1 != 0 {
+            volumes.push(query_volume(format!("{}:\\", ch)));
+        }
+        mask >>= 1;
+    }
+
+    volumes
+}
+
+fn query_volume(root: String) -> Volume {
+    let root_param = HSTRING::from(&root);
+
+    let mut label = [0u16; NAME_BUFFER_LEN];
+    let mut file_system = [0u16; NAME_BUFFER_LEN];
+    let mut serial = 0u32;
+    // Safety: the buffers outlive the call and their length is passed along
+    let info = unsafe {
+        GetVolumeInformationW(
+            &root_param,
+            Some(&mut label),
+            Some(&mut serial),
+            None,
+            None,
+            Some(&mut file_system),
+        )
+    };
+
+    let (mut free_bytes, mut total_bytes) = (0u64, 0u64);
+    // Safety: !
+    let _ = unsafe {
+        GetDiskFreeSpaceExW(
+            &root_param,
+            Some(&mut free_bytes),
+            Some(&mut total_bytes),
+            None,
+        )
+    };
+
+    // Safety: !
+    let removable = unsafe { GetDriveTypeW(&root_param) } == DRIVE_REMOVABLE;
+
+    let (label, serial, file_system) = match info {
+        Ok(()) => (
+            Some(from_wide(&label)).filter(|label| !label.is_empty()),
+            // formatted the same way 'vol' does
+            Some(format!("{:04X}-{:04X}", serial >> 16, serial & 0xFFFF)),
+            FileSystem::from_name(&from_wide(&file_system)),
+        ),
+        // there is no media in the drive (e.g. an empty card reader)
+        Err(_) => (None, None, FileSystem::Other(String::new())),
+    };
+
+    Volume {
+        mount_path: PathBuf::from(root),
+        file_system,
+        label,
+        serial,
+        total_bytes,
+        free_bytes,
+        removable,
+    }
+}
+
+fn from_wide(buffer: &[u16]) -> String {
+    let len = buffer
+        .iter()
+        .position(|ch| *ch == 0)
+        .unwrap_or(buffer.len());
+    String::from_utf16_lossy(&buffer[..len])
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::list_volumes;
+
+    #[test]
+    fn check_for_c_drive() {
+        assert!(list_volumes()
+            .iter()
+            .any(|volume| volume.mount_path == PathBuf::from("C:\\")))
+    }
+}